@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX post_visibility_idx;
+
+ALTER TABLE posts
+DROP COLUMN status;
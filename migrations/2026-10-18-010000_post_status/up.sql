@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE posts
+ADD COLUMN status VARCHAR NOT NULL DEFAULT 'published'
+    CONSTRAINT post_status_constraint CHECK ( status IN ('draft', 'scheduled', 'published') );
+
+CREATE INDEX post_visibility_idx ON posts (status, public_date);
@@ -0,0 +1,22 @@
+-- Your SQL goes here
+CREATE TABLE revisions (
+    id SERIAL PRIMARY KEY,
+    model_type VARCHAR NOT NULL
+        CONSTRAINT revision_model_type_constraint CHECK ( model_type IN ('post', 'page') ),
+    model_id INTEGER NOT NULL,
+    title VARCHAR NOT NULL,
+    content TEXT NOT NULL,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc'),
+    finger_print VARCHAR NOT NULL
+);
+
+CREATE INDEX revision_model_idx ON revisions (model_type, model_id, created_at);
+
+INSERT INTO revisions (model_type, model_id, title, content, tags, created_at, finger_print)
+SELECT 'post', id, title, content, tags, update_date, 'unknown'
+FROM posts;
+
+INSERT INTO revisions (model_type, model_id, title, content, finger_print)
+SELECT 'page', id, title, content, 'unknown'
+FROM pages;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE slug_aliases;
+
+ALTER TABLE pages
+DROP COLUMN slug;
+
+ALTER TABLE posts
+DROP COLUMN slug;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+ALTER TABLE posts
+ADD COLUMN slug VARCHAR;
+
+UPDATE posts
+    SET slug = translate(lower(title), ' ', '-');
+
+ALTER TABLE posts
+ALTER COLUMN slug SET NOT NULL,
+ADD CONSTRAINT post_slug_unique UNIQUE (slug),
+ADD CONSTRAINT post_slug_constraint CHECK ( slug ~ '^[^/[:space:]]+$' );
+
+ALTER TABLE pages
+ADD COLUMN slug VARCHAR;
+
+UPDATE pages
+    SET slug = translate(lower(title), ' ', '-');
+
+ALTER TABLE pages
+ALTER COLUMN slug SET NOT NULL,
+ADD CONSTRAINT page_slug_unique UNIQUE (slug),
+ADD CONSTRAINT page_slug_constraint CHECK ( slug ~ '^[^/[:space:]]+$' );
+
+CREATE TABLE slug_aliases (
+    id SERIAL PRIMARY KEY,
+    model_type VARCHAR NOT NULL
+        CONSTRAINT slug_alias_model_type_constraint CHECK ( model_type IN ('post', 'page') ),
+    model_id INTEGER NOT NULL,
+    slug VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc'),
+    CONSTRAINT slug_alias_unique UNIQUE (model_type, slug)
+);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE posts
+DROP CONSTRAINT post_title_name_constraint;
+
+ALTER TABLE pages
+DROP CONSTRAINT page_title_name_constraint;
+
+ALTER TABLE posts
+ADD CONSTRAINT post_title_name_constraint CHECK ( title ~* '^[ \da-zA-Z]+$' );
+
+ALTER TABLE pages
+ADD CONSTRAINT page_title_name_constraint CHECK ( title ~* '^[ \da-zA-Z]+$' );
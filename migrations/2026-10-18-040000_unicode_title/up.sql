@@ -0,0 +1,12 @@
+-- Your SQL goes here
+ALTER TABLE posts
+DROP CONSTRAINT post_title_name_constraint;
+
+ALTER TABLE pages
+DROP CONSTRAINT page_title_name_constraint;
+
+ALTER TABLE posts
+ADD CONSTRAINT post_title_name_constraint CHECK ( title !~ '^[[:space:]]*$' );
+
+ALTER TABLE pages
+ADD CONSTRAINT page_title_name_constraint CHECK ( title !~ '^[[:space:]]*$' );
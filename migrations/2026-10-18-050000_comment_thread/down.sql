@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX comment_thread_idx;
+
+ALTER TABLE comments
+DROP COLUMN created_at,
+DROP COLUMN parent_id;
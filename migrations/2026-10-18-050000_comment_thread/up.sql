@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE comments
+ADD COLUMN parent_id INTEGER REFERENCES comments (id) ON DELETE SET NULL,
+ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc');
+
+CREATE INDEX comment_thread_idx ON comments (post_id, created_at);
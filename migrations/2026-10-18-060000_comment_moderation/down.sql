@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE approved_finger_prints;
+
+ALTER TABLE comments
+DROP COLUMN approved;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+ALTER TABLE comments
+ADD COLUMN approved BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE approved_finger_prints (
+    finger_print VARCHAR PRIMARY KEY,
+    approved_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc')
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE commenter_keys (
+    finger_print VARCHAR PRIMARY KEY,
+    key_ids TEXT[] NOT NULL,
+    user_id VARCHAR NOT NULL,
+    armored_key TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc')
+);
+
+CREATE INDEX commenter_key_id_idx ON commenter_keys USING GIN (key_ids);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM comments WHERE NOT signed;
+ALTER TABLE comments DROP COLUMN signed;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE api_nonces (
+    time_stamp BIGINT NOT NULL,
+    nonce VARCHAR NOT NULL,
+    PRIMARY KEY (time_stamp, nonce)
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE admin_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL UNIQUE,
+    role VARCHAR NOT NULL CHECK (role IN ('owner', 'editor', 'moderator')),
+    finger_print VARCHAR NOT NULL UNIQUE,
+    public_key TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc'),
+    revoked_at TIMESTAMP
+);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE audit_logs (
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc'),
+    finger_print VARCHAR NOT NULL,
+    operation VARCHAR NOT NULL,
+    model_type VARCHAR,
+    model_id INTEGER,
+    success BOOLEAN NOT NULL,
+    message TEXT,
+    client_ip VARCHAR
+);
+
+CREATE INDEX audit_log_time_idx ON audit_logs (created_at);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE rendered_html (
+    model_type VARCHAR NOT NULL,
+    model_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    abstract_content TEXT,
+    rendered_at TIMESTAMP NOT NULL DEFAULT (now() at time zone 'utc'),
+    PRIMARY KEY (model_type, model_id)
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE posts ADD COLUMN toc BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- The gpg formats cannot be recovered, the rewritten fingerprints are kept.
+SELECT 1;
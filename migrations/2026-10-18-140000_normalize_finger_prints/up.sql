@@ -0,0 +1,27 @@
+-- Your SQL goes here
+-- gpg recorded either the spaced fingerprint or, for ultimately trusted keys, the quoted
+-- user id; rewrite both into the uppercase fingerprints reported by the keyring.
+UPDATE comments
+SET finger_print = upper(replace(finger_print, ' ', ''))
+WHERE signed AND finger_print ~ '^[0-9A-Fa-f ]+$';
+
+UPDATE comments
+SET finger_print = k.finger_print
+FROM commenter_keys k
+WHERE comments.signed AND comments.finger_print = '"' || k.user_id || '" [ultimate]';
+
+INSERT INTO approved_finger_prints (finger_print, approved_at)
+SELECT upper(replace(finger_print, ' ', '')), approved_at
+FROM approved_finger_prints
+WHERE finger_print ~ '^[0-9A-Fa-f ]+$'
+ON CONFLICT DO NOTHING;
+
+INSERT INTO approved_finger_prints (finger_print, approved_at)
+SELECT k.finger_print, a.approved_at
+FROM approved_finger_prints a
+JOIN commenter_keys k ON a.finger_print = '"' || k.user_id || '" [ultimate]'
+ON CONFLICT DO NOTHING;
+
+DELETE FROM approved_finger_prints a
+WHERE (a.finger_print ~ '^[0-9A-Fa-f ]+$' AND a.finger_print <> upper(replace(a.finger_print, ' ', '')))
+   OR EXISTS (SELECT 1 FROM commenter_keys k WHERE a.finger_print = '"' || k.user_id || '" [ultimate]');
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- html cached before this column was rendered without highlighting, version 0 is never served
+ALTER TABLE rendered_html ADD COLUMN renderer_version INTEGER NOT NULL DEFAULT 0;
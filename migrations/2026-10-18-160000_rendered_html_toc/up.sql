@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE rendered_html ADD COLUMN table_of_contents JSONB;
+-- cached html has heading ids of an older renderer and no table of contents stored with it
+DELETE FROM rendered_html;
@@ -1,10 +1,13 @@
 use async_diesel::*;
 use chrono::Utc;
-use diesel::{ExpressionMethods, QueryDsl};
+use diesel::{Connection, ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
 
 use crate::api::JsonResponse::*;
-use crate::ConnPool;
-use crate::model::{Comment, NewPageRaw, Post, Page, POST_COLUMNS, NewPostRaw};
+use crate::{ConnPool, FEED_ABSTRACT};
+use crate::crypto::Algorithm;
+use crate::markdown::{Heading, RENDERER_VERSION};
+use crate::model::{AdminRole, AuditLog, NewAdminKey, NewCommenterKey, Comment, NewPageRaw, Post, Page, POST_COLUMNS, NewPostRaw, PostStatus, Revision, NewRevision};
+use crate::utils::{normalize_tags, slugify};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[serde(tag = "request_type", content = "request_body")]
@@ -14,12 +17,34 @@ pub enum JsonRequest {
         title: Option<String>,
         tags: Option<Vec<String>>,
         content: Option<String>,
+        #[serde(default)]
+        slug: Option<String>,
+        /// Refuse the update when the post has been changed after this `update_date`.
+        #[serde(default)]
+        if_unmodified_since: Option<chrono::NaiveDateTime>,
+        #[serde(default)]
+        toc: Option<bool>,
+        /// `Some(true)` turns the post back into a draft, `Some(false)` publishes a draft now.
+        #[serde(default)]
+        draft: Option<bool>,
     },
     PostSearch(String),
     PostCreate {
         title: String,
         content: String,
         tag: Vec<String>,
+        #[serde(default)]
+        draft: bool,
+        #[serde(default)]
+        publish_at: Option<chrono::NaiveDateTime>,
+        #[serde(default)]
+        slug: Option<String>,
+        #[serde(default)]
+        toc: Option<bool>,
+    },
+    PostPublish {
+        id: i32,
+        publish_at: Option<chrono::NaiveDateTime>,
     },
     PostComments(i32),
     PageUpdate {
@@ -28,29 +53,170 @@ pub enum JsonRequest {
         content: Option<String>,
         important: Option<bool>,
         description: Option<String>,
+        #[serde(default)]
+        slug: Option<String>,
+        /// Refuse the update unless the page still has this digest; pages keep no update date.
+        #[serde(default)]
+        expected_digest: Option<String>,
     },
     PageCreate {
         title: String,
         content: String,
         description: String,
         important: bool,
+        #[serde(default)]
+        slug: Option<String>,
     },
     ListOperation { list_type: ModelType },
     CheckOperation { id: i32, check_type: ModelType },
     DeleteOperation { id: i32, delete_type: ModelType },
+    ListRevisions { id: i32, revision_type: ModelType },
+    CheckRevision(i32),
+    RestoreRevision(i32),
+    ListPendingComments,
+    ApproveComment(i32),
+    RejectComment(i32),
+    AddCommenterKey(String),
+    ListCommenterKeys,
+    RemoveCommenterKey(String),
+    AddAdminKey {
+        name: String,
+        role: AdminRole,
+        public_key: String,
+    },
+    ListAdminKeys,
+    RevokeAdminKey(String),
+    AuditLog {
+        since: Option<chrono::NaiveDateTime>,
+        limit: Option<i64>,
+    },
+    ExportSite,
+    ImportSite(SiteArchive),
+    PostImport(Vec<ImportedPost>),
+    ContentDigests,
+    /// Several requests applied in one transaction; nothing is kept unless all of them succeed.
+    Batch(Vec<JsonRequest>),
+    Preview {
+        kind: ModelType,
+        title: String,
+        content: String,
+        #[serde(default)]
+        tags: Vec<String>,
+    },
+    /// Render every post and page again, for when the renderer has changed.
+    RebuildCache,
 }
 
+/// A post converted from another blog engine, keeping its original date.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ImportedPost {
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub public_date: Option<chrono::NaiveDateTime>,
+    pub draft: bool,
+    pub slug: Option<String>,
+}
+
+/// Everything needed to rebuild a blog on another server.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct SiteArchive {
+    pub posts: Vec<Post>,
+    pub pages: Vec<Page>,
+    pub comments: Vec<Comment>,
+}
+
+/// Identifies a post or a page by what `client sync` can edit: title, slug, tags or page
+/// attributes, whether a post is a draft, and content. Dates are left out.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+pub struct ContentDigest {
+    pub model_type: ModelType,
+    pub id: i32,
+    pub title: String,
+    pub slug: String,
+    pub digest: String,
+    /// `update_date` of a post, so that syncing does not overwrite a change made after the digest.
+    #[serde(default)]
+    pub update_date: Option<chrono::NaiveDateTime>,
+}
+
+pub fn post_digest(title: &str, slug: &str, tags: &[String], toc: bool, draft: bool, content: &str) -> anyhow::Result<String> {
+    crate::utils::content_digest([title, slug, if toc { "1" } else { "0" }, if draft { "1" } else { "0" }, content].iter()
+        .copied()
+        .chain(tags.iter().map(String::as_str)))
+}
+
+pub fn page_digest(title: &str, slug: &str, description: &str, important: bool, content: &str) -> anyhow::Result<String> {
+    crate::utils::content_digest(&[title, slug, description, if important { "1" } else { "0" }, content])
+}
+
+impl ContentDigest {
+    pub fn of_post(post: &Post) -> anyhow::Result<Self> {
+        Ok(ContentDigest {
+            model_type: ModelType::Post,
+            id: post.id,
+            title: post.title.clone(),
+            slug: post.slug.clone(),
+            digest: post_digest(post.title.as_str(), post.slug.as_str(), post.tags.as_slice(), post.toc,
+                                post.status == PostStatus::Draft, post.content.as_str())?,
+            update_date: Some(post.update_date),
+        })
+    }
+
+    pub fn of_page(page: &Page) -> anyhow::Result<Self> {
+        Ok(ContentDigest {
+            model_type: ModelType::Page,
+            id: page.id,
+            title: page.title.clone(),
+            slug: page.slug.clone(),
+            digest: page_digest(page.title.as_str(), page.slug.as_str(), page.description.as_str(),
+                                page.important, page.content.as_str())?,
+            update_date: None,
+        })
+    }
+}
+
+/// Rows per insert statement when importing, well below the bind parameter limit.
+const IMPORT_CHUNK: usize = 500;
+
+/// Audit log entries returned when the request gives no limit.
+const AUDIT_LIMIT: i64 = 100;
+
+/// The most audit log entries one request gets, whatever limit it asks for.
+const MAX_AUDIT_LIMIT: i64 = 1000;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[serde(tag = "response_type", content = "response_body")]
 pub enum JsonResponse {
-    PostList(Vec<(i32, String, chrono::NaiveDateTime, chrono::NaiveDateTime)>),
+    PostList(Vec<(i32, String, String, chrono::NaiveDateTime, chrono::NaiveDateTime, PostStatus)>),
     PostSearchList(Vec<Post>),
     CommentList(Vec<Comment>),
-    PageList(Vec<(i32, String)>),
+    PageList(Vec<(i32, String, String)>),
     PostInfo(Post),
     PageInfo(Page),
     CommentInfo(Comment),
+    RevisionList(Vec<(i32, String, chrono::NaiveDateTime, String)>),
+    RevisionInfo(Revision),
+    CommenterKeyList(Vec<(String, String, chrono::NaiveDateTime)>),
+    AuditLogList(Vec<AuditLog>),
+    SiteExport(SiteArchive),
+    ImportReport {
+        created: Vec<(i32, String)>,
+        conflicts: Vec<String>,
+    },
+    ContentDigestList(Vec<ContentDigest>),
+    PreviewResult {
+        content: String,
+        abstract_content: Option<String>,
+        #[serde(default)]
+        table_of_contents: Option<Vec<Heading>>,
+    },
+    /// One result per executed item of a batch, stopping at the first failure.
+    BatchResult {
+        committed: bool,
+        results: Vec<JsonResponse>,
+    },
+    AdminKeyList(Vec<(i32, String, AdminRole, String, chrono::NaiveDateTime, Option<chrono::NaiveDateTime>)>),
     Error(String),
     Success(usize),
 }
@@ -59,13 +225,23 @@ impl JsonResponse {
     pub fn get_raw_content(&self) -> anyhow::Result<&str> {
         match self {
             PostInfo(Post { content, .. }) | CommentInfo(Comment { content, .. }) | PageInfo(Page { content, .. })
+            | RevisionInfo(Revision { content, .. })
             => Ok(content.as_str()),
             _ => Err(anyhow::anyhow!("cannot get raw content from server reply"))
         }
     }
+
+    /// The failure a response reports; a batch fails with its first failed item.
+    pub fn error(&self) -> Option<&str> {
+        match self {
+            Error(e) => Some(e.as_str()),
+            BatchResult { results, .. } => results.iter().find_map(JsonResponse::error),
+            _ => None
+        }
+    }
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
 #[serde(tag = "tag", content = "content")]
 pub enum ModelType {
     Post,
@@ -73,190 +249,841 @@ pub enum ModelType {
     Comment,
 }
 
+impl ModelType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ModelType::Post => "post",
+            ModelType::Page => "page",
+            ModelType::Comment => "comment",
+        }
+    }
+}
+
 impl<T: ToString> From<T> for JsonResponse {
     fn from(e: T) -> Self {
         Error(e.to_string())
     }
 }
 
+/// Decide the public date and status of a post that should go live at `publish_at`,
+/// falling back to `now` when no time is given.
+fn publish_state(now: chrono::NaiveDateTime, publish_at: Option<chrono::NaiveDateTime>)
+                 -> (chrono::NaiveDateTime, PostStatus) {
+    match publish_at {
+        Some(time) if time > now => (time, PostStatus::Scheduled),
+        Some(time) => (time, PostStatus::Published),
+        None => (now, PostStatus::Published)
+    }
+}
+
+/// Store a snapshot for every written row; returns the number of snapshots.
+fn save_revisions<I: IntoIterator<Item=NewRevision>>(conn: &PgConnection, revisions: I)
+                                                      -> diesel::QueryResult<usize> {
+    use crate::schema::revisions::dsl as r;
+    let mut count = 0;
+    for revision in revisions {
+        count += diesel::insert_into(r::revisions)
+            .values(revision)
+            .execute(conn)?;
+    }
+    Ok(count)
+}
+
+/// Keep `old_slug` resolving to the model after its slug has been changed to `new_slug`.
+fn save_slug_alias(conn: &PgConnection, model_type: &str, model_id: i32, old_slug: &str, new_slug: &str)
+                   -> diesel::QueryResult<()> {
+    use crate::schema::slug_aliases::dsl as a;
+    if old_slug == new_slug {
+        return Ok(());
+    }
+    diesel::delete(a::slug_aliases
+        .filter(a::model_type.eq(model_type))
+        .filter(a::slug.eq(old_slug)))
+        .execute(conn)?;
+    diesel::insert_into(a::slug_aliases)
+        .values((a::model_type.eq(model_type), a::model_id.eq(model_id), a::slug.eq(old_slug)))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Move the comments and approvals that the old gpg verifier recorded under the user id of
+/// `key` over to its fingerprint.
+fn adopt_legacy_finger_print(conn: &PgConnection, key: &NewCommenterKey) -> diesel::QueryResult<()> {
+    use crate::schema::approved_finger_prints::dsl as f;
+    use crate::schema::comments::dsl as m;
+    let legacy = crate::keyring::legacy_finger_print(key.user_id.as_str());
+    diesel::update(m::comments
+        .filter(m::signed.eq(true))
+        .filter(m::finger_print.eq(legacy.as_str())))
+        .set(m::finger_print.eq(key.finger_print.as_str()))
+        .execute(conn)?;
+    let approved = diesel::delete(f::approved_finger_prints
+        .filter(f::finger_print.eq(legacy.as_str())))
+        .execute(conn)?;
+    if approved > 0 {
+        diesel::insert_into(f::approved_finger_prints)
+            .values(f::finger_print.eq(key.finger_print.as_str()))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Drop the revisions and old slugs of a post or page that is being deleted.
+fn forget_history(conn: &PgConnection, model_type: &str, model_id: i32) -> diesel::QueryResult<()> {
+    use crate::schema::revisions::dsl as r;
+    use crate::schema::slug_aliases::dsl as a;
+    diesel::delete(r::revisions
+        .filter(r::model_type.eq(model_type))
+        .filter(r::model_id.eq(model_id)))
+        .execute(conn)?;
+    diesel::delete(a::slug_aliases
+        .filter(a::model_type.eq(model_type))
+        .filter(a::model_id.eq(model_id)))
+        .execute(conn)
+        .map(|_| ())
+}
+
+fn site_is_empty(conn: &PgConnection) -> diesel::QueryResult<bool> {
+    use crate::schema::comments::dsl as m;
+    use crate::schema::pages::dsl as g;
+    use crate::schema::posts::dsl as p;
+    use diesel::dsl::{exists, select};
+    Ok(!select(exists(p::posts.select(p::id))).get_result::<bool>(conn)?
+        && !select(exists(g::pages.select(g::id))).get_result::<bool>(conn)?
+        && !select(exists(m::comments.select(m::id))).get_result::<bool>(conn)?)
+}
+
+/// Store the html of a post or page as it is written, so that serving it renders nothing.
+fn save_rendered(conn: &PgConnection, model_type: &str, model_id: i32, content: String,
+                 abstract_content: Option<String>, table_of_contents: Option<Vec<Heading>>)
+                 -> diesel::QueryResult<()> {
+    use crate::schema::rendered_html::dsl as h;
+    let table_of_contents = table_of_contents
+        .map(serde_json::to_value)
+        .transpose()
+        .map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+    forget_rendered(conn, model_type, model_id)?;
+    diesel::insert_into(h::rendered_html)
+        .values((h::model_type.eq(model_type), h::model_id.eq(model_id), h::content.eq(content),
+                 h::abstract_content.eq(abstract_content), h::renderer_version.eq(RENDERER_VERSION),
+                 h::table_of_contents.eq(table_of_contents)))
+        .execute(conn)
+        .map(|_| ())
+}
+
+fn forget_rendered(conn: &PgConnection, model_type: &str, model_id: i32) -> diesel::QueryResult<usize> {
+    use crate::schema::rendered_html::dsl as h;
+    diesel::delete(h::rendered_html
+        .filter(h::model_type.eq(model_type))
+        .filter(h::model_id.eq(model_id)))
+        .execute(conn)
+}
+
+fn save_rendered_post(conn: &PgConnection, post: &Post) -> diesel::QueryResult<()> {
+    let (content, table_of_contents) = post.render_with_contents();
+    save_rendered(conn, "post", post.id, content, Some(post.render_abstract(FEED_ABSTRACT)), table_of_contents)
+}
+
+fn save_rendered_page(conn: &PgConnection, page: &Page) -> diesel::QueryResult<()> {
+    save_rendered(conn, "page", page.id, page.render_content(), None, None)
+}
+
 impl JsonRequest {
-    pub async fn handle(self, conn: &ConnPool) -> JsonResponse {
+    /// Name of the variant as it appears in the `request_type` tag.
+    pub fn operation(&self) -> &'static str {
         use JsonRequest::*;
-        let conn = &conn;
         match self {
-            PostUpdate { id, title, tags, content } => {
+            PostUpdate { .. } => "PostUpdate",
+            PostSearch(_) => "PostSearch",
+            PostCreate { .. } => "PostCreate",
+            PostPublish { .. } => "PostPublish",
+            PostComments(_) => "PostComments",
+            PageUpdate { .. } => "PageUpdate",
+            PageCreate { .. } => "PageCreate",
+            ListOperation { .. } => "ListOperation",
+            CheckOperation { .. } => "CheckOperation",
+            DeleteOperation { .. } => "DeleteOperation",
+            ListRevisions { .. } => "ListRevisions",
+            CheckRevision(_) => "CheckRevision",
+            RestoreRevision(_) => "RestoreRevision",
+            ListPendingComments => "ListPendingComments",
+            ApproveComment(_) => "ApproveComment",
+            RejectComment(_) => "RejectComment",
+            AddCommenterKey(_) => "AddCommenterKey",
+            ListCommenterKeys => "ListCommenterKeys",
+            RemoveCommenterKey(_) => "RemoveCommenterKey",
+            AddAdminKey { .. } => "AddAdminKey",
+            ListAdminKeys => "ListAdminKeys",
+            RevokeAdminKey(_) => "RevokeAdminKey",
+            AuditLog { .. } => "AuditLog",
+            ExportSite => "ExportSite",
+            ImportSite(_) => "ImportSite",
+            PostImport(_) => "PostImport",
+            ContentDigests => "ContentDigests",
+            Batch(_) => "Batch",
+            Preview { .. } => "Preview",
+            RebuildCache => "RebuildCache",
+        }
+    }
+
+    /// The kind of model and the id a request works on, as far as the request tells.
+    pub fn target(&self) -> (Option<&'static str>, Option<i32>) {
+        use JsonRequest::*;
+        match self {
+            PostUpdate { id, .. } | PostPublish { id, .. } | PostComments(id) => (Some("post"), Some(*id)),
+            PostCreate { .. } | PostSearch(_) | PostImport(_) => (Some("post"), None),
+            PageUpdate { id, .. } => (Some("page"), Some(*id)),
+            PageCreate { .. } => (Some("page"), None),
+            ListOperation { list_type } => (Some(list_type.as_str()), None),
+            CheckOperation { id, check_type: t } | DeleteOperation { id, delete_type: t }
+            | ListRevisions { id, revision_type: t } => (Some(t.as_str()), Some(*id)),
+            CheckRevision(id) | RestoreRevision(id) => (Some("revision"), Some(*id)),
+            ApproveComment(id) | RejectComment(id) => (Some("comment"), Some(*id)),
+            ListPendingComments => (Some("comment"), None),
+            AddCommenterKey(_) | ListCommenterKeys | RemoveCommenterKey(_) => (Some("commenter_key"), None),
+            AddAdminKey { .. } | ListAdminKeys | RevokeAdminKey(_) => (Some("admin_key"), None),
+            AuditLog { .. } => (Some("audit_log"), None),
+            ExportSite | ImportSite(_) | ContentDigests | RebuildCache => (Some("site"), None),
+            Batch(_) => (None, None),
+            Preview { kind, .. } => (Some(kind.as_str()), None),
+        }
+    }
+
+    /// Whether a key with `role` may send this request.
+    pub fn permitted(&self, role: AdminRole) -> bool {
+        use JsonRequest::*;
+        if let Batch(requests) = self {
+            return requests.iter().all(|x| x.permitted(role));
+        }
+        match role {
+            AdminRole::Owner => true,
+            AdminRole::Editor => match self {
+                PostUpdate { .. } | PostSearch(_) | PostCreate { .. } | PostPublish { .. } | PostImport(_)
+                | PageUpdate { .. } | PageCreate { .. }
+                | ListRevisions { .. } | CheckRevision(_) | RestoreRevision(_) | ContentDigests => true,
+                Preview { kind: t, .. } => !matches!(t, ModelType::Comment),
+                ListOperation { list_type: t } | CheckOperation { check_type: t, .. } => !matches!(t, ModelType::Comment),
+                _ => false
+            },
+            AdminRole::Moderator => match self {
+                PostSearch(_) | PostComments(_)
+                | ListPendingComments | ApproveComment(_) | RejectComment(_)
+                | AddCommenterKey(_) | ListCommenterKeys | RemoveCommenterKey(_) => true,
+                ListOperation { list_type: ModelType::Post } => true,
+                CheckOperation { check_type: t, .. } => !matches!(t, ModelType::Page),
+                DeleteOperation { delete_type: ModelType::Comment, .. } => true,
+                _ => false
+            },
+        }
+    }
+
+    /// Admin keys must use the algorithm of the server key, otherwise no request signed with
+    /// them could ever be decoded.
+    fn check_admin_keys(&self, server_algorithm: Algorithm) -> Result<(), String> {
+        use JsonRequest::*;
+        match self {
+            Batch(requests) => requests.iter()
+                .try_for_each(|x| x.check_admin_keys(server_algorithm)),
+            AddAdminKey { public_key, .. } => {
+                let algorithm = crate::crypto::PublicKey::load(public_key.as_str())
+                    .and_then(|x| x.algorithm())
+                    .map_err(|e| e.to_string())?;
+                if algorithm == server_algorithm {
+                    Ok(())
+                } else {
+                    Err(format!("the server key is {:?} but the admin key is {:?}", server_algorithm, algorithm))
+                }
+            }
+            _ => Ok(())
+        }
+    }
+
+    pub async fn handle(self, conn: &ConnPool, finger_print: &str, role: AdminRole,
+                        server_algorithm: Algorithm) -> JsonResponse {
+        if !self.permitted(role) {
+            return Error(format!("{} keys are not allowed to perform this operation", role.as_str()));
+        }
+        if let Err(e) = self.check_admin_keys(server_algorithm) {
+            return Error(e);
+        }
+        let finger_print = finger_print.to_string();
+        conn.transaction(move |c| self.execute(c, finger_print.as_str()))
+            .await
+            .unwrap_or_else(Into::into)
+    }
+
+    /// Run the request on a connection that is already inside a transaction; a database error
+    /// aborts the whole transaction, other failures are reported as `Error`.
+    fn execute(self, c: &PgConnection, finger_print: &str) -> diesel::QueryResult<JsonResponse> {
+        use JsonRequest::*;
+        Ok(match self {
+            PostUpdate { id, title, tags, content, slug, if_unmodified_since, toc, draft } => {
                 use crate::schema::posts::dsl as p;
                 let time = Utc::now().naive_local();
+                let (old_slug, update_date, old_status): (String, chrono::NaiveDateTime, PostStatus) = p::posts
+                    .select((p::slug, p::update_date, p::status))
+                    .filter(p::id.eq(id))
+                    .for_update()
+                    .first(c)?;
+                if if_unmodified_since.map(|x| x != update_date).unwrap_or(false) {
+                    return Ok(Error(format!("post {} has been modified at {}", id, update_date)));
+                }
+                let slug = slug.map(slugify);
+                if slug.as_deref() == Some("") {
+                    return Ok(Error(format!("empty slug for post {}", id)));
+                }
+                let (public_date, status) = match draft {
+                    Some(true) => (None, Some(PostStatus::Draft)),
+                    Some(false) if old_status == PostStatus::Draft => {
+                        let (public_date, status) = publish_state(time, None);
+                        (Some(public_date), Some(status))
+                    }
+                    _ => (None, None)
+                };
                 let change_set = NewPostRaw {
                     title,
                     update_date: Some(time),
-                    public_date: None,
-                    tags,
+                    public_date,
+                    tags: tags.map(normalize_tags),
                     content,
+                    status,
+                    slug,
+                    toc,
                 };
-                diesel::update(p::posts.filter(p::id.eq(id)))
+                let updated: Vec<Post> = diesel::update(p::posts.filter(p::id.eq(id)))
                     .set(change_set)
-                    .execute_async(conn)
-                    .await
-                    .map(|s| Success(s))
-                    .unwrap_or_else(Into::into)
+                    .returning(POST_COLUMNS)
+                    .get_results(c)?;
+                for post in updated.iter() {
+                    save_slug_alias(c, "post", post.id, old_slug.as_str(), post.slug.as_str())?;
+                    save_rendered_post(c, post)?;
+                }
+                Success(save_revisions(c, updated.iter()
+                    .map(|x| NewRevision::of_post(x, finger_print)))?)
             }
             PostSearch(search) => {
-                conn.get().map(|x|
-                    Post::list(&x, search.as_str(), None)
-                        .map(|x| PostSearchList(x))
-                        .unwrap_or_else(Into::into)
-                ).unwrap_or_else(Into::into)
+                Post::list(c, search.as_str(), None, false)
+                    .map(|x| PostSearchList(x))
+                    .unwrap_or_else(Into::into)
             }
-            PageCreate { title, content, important, description } => {
+            PageCreate { title, content, important, description, slug } => {
                 use crate::schema::pages::dsl as p;
-                diesel::insert_into(p::pages)
-                    .values(NewPageRaw {
-                        title: Some(title),
-                        content: Some(content),
-                        important: Some(important),
-                        description: Some(description),
-                    })
-                    .execute_async(conn)
-                    .await
-                    .map(|s| Success(s))
-                    .unwrap_or_else(Into::into)
+                let slug = slugify(slug.as_ref().unwrap_or(&title));
+                if slug.is_empty() {
+                    return Ok(Error(format!("empty slug for page {}", title)));
+                }
+                let new_page = NewPageRaw {
+                    title: Some(title),
+                    content: Some(content),
+                    important: Some(important),
+                    description: Some(description),
+                    slug: Some(slug),
+                };
+                let page: Page = diesel::insert_into(p::pages)
+                    .values(new_page)
+                    .get_result(c)?;
+                save_rendered_page(c, &page)?;
+                Success(save_revisions(c, Some(NewRevision::of_page(&page, finger_print)))?)
             }
-            PageUpdate { id, title, content, important, description } => {
+            PageUpdate { id, title, content, important, description, slug, expected_digest } => {
                 use crate::schema::pages::dsl as p;
+                let slug = slug.map(slugify);
+                if slug.as_deref() == Some("") {
+                    return Ok(Error(format!("empty slug for page {}", id)));
+                }
                 let change_set = NewPageRaw {
                     title,
                     content,
                     important,
                     description,
+                    slug,
                 };
-                diesel::update(p::pages.filter(p::id.eq(id)))
+                let current: Page = p::pages
+                    .filter(p::id.eq(id))
+                    .for_update()
+                    .first(c)?;
+                if let Some(expected) = expected_digest {
+                    let digest = match ContentDigest::of_page(&current) {
+                        Ok(x) => x.digest,
+                        Err(e) => return Ok(e.into())
+                    };
+                    if digest != expected {
+                        return Ok(Error(format!("page {} has been modified", id)));
+                    }
+                }
+                let old_slug = current.slug;
+                let updated: Vec<Page> = diesel::update(p::pages.filter(p::id.eq(id)))
                     .set(change_set)
-                    .execute_async(conn)
-                    .await
-                    .map(|s| Success(s))
-                    .unwrap_or_else(Into::into)
+                    .get_results(c)?;
+                for page in updated.iter() {
+                    save_slug_alias(c, "page", page.id, old_slug.as_str(), page.slug.as_str())?;
+                    save_rendered_page(c, page)?;
+                }
+                Success(save_revisions(c, updated.iter()
+                    .map(|x| NewRevision::of_page(x, finger_print)))?)
             }
-            PostCreate { title, content, tag } => {
+            PostCreate { title, content, tag, draft, publish_at, slug, toc } => {
                 use crate::schema::posts::dsl as p;
                 let time = Utc::now().naive_local();
-                let mut tag: Vec<String> = tag.iter()
-                    .map(|x| x.trim().to_ascii_lowercase()).collect();
-                tag.sort();
-                tag.dedup_by(|x, y| x == y);
-                diesel::insert_into(p::posts)
-                    .values(NewPostRaw {
-                        title: Some(title),
-                        public_date: Some(time),
-                        update_date: Some(time),
-                        tags: Some(tag),
-                        content: Some(content),
-                    })
-                    .execute_async(conn)
-                    .await
-                    .map(|s| Success(s))
-                    .unwrap_or_else(Into::into)
+                let tag = normalize_tags(tag);
+                let (public_date, status) = publish_state(time, publish_at);
+                let slug = slugify(slug.as_ref().unwrap_or(&title));
+                if slug.is_empty() {
+                    return Ok(Error(format!("empty slug for post {}", title)));
+                }
+                let new_post = NewPostRaw {
+                    title: Some(title),
+                    public_date: Some(public_date),
+                    update_date: Some(time),
+                    tags: Some(tag),
+                    content: Some(content),
+                    status: Some(if draft { PostStatus::Draft } else { status }),
+                    slug: Some(slug),
+                    toc,
+                };
+                let post: Post = diesel::insert_into(p::posts)
+                    .values(new_post)
+                    .returning(POST_COLUMNS)
+                    .get_result(c)?;
+                save_rendered_post(c, &post)?;
+                Success(save_revisions(c, Some(NewRevision::of_post(&post, finger_print)))?)
+            }
+            PostImport(posts) => {
+                use crate::schema::posts::dsl as p;
+                let time = Utc::now().naive_local();
+                let existing: Vec<(String, String)> = p::posts
+                    .select((p::title, p::slug))
+                    .load(c)?;
+                // titles are compared without case, like the citext column does
+                let mut titles: hashbrown::HashSet<String> = existing.iter()
+                    .map(|x| x.0.to_lowercase())
+                    .collect();
+                let mut slugs: hashbrown::HashSet<String> = existing.into_iter()
+                    .map(|x| x.1)
+                    .collect();
+                let mut created = Vec::new();
+                let mut conflicts = Vec::new();
+                for post in posts {
+                    let slug = slugify(post.slug.as_ref().unwrap_or(&post.title));
+                    if slug.is_empty() || titles.contains(&post.title.to_lowercase()) || slugs.contains(&slug) {
+                        conflicts.push(post.title);
+                        continue;
+                    }
+                    titles.insert(post.title.to_lowercase());
+                    slugs.insert(slug.clone());
+                    let (public_date, status) = publish_state(time, post.public_date);
+                    let new_post = NewPostRaw {
+                        title: Some(post.title),
+                        public_date: Some(public_date),
+                        update_date: Some(public_date.min(time)),
+                        tags: Some(normalize_tags(post.tags)),
+                        content: Some(post.content),
+                        status: Some(if post.draft { PostStatus::Draft } else { status }),
+                        slug: Some(slug),
+                        toc: None,
+                    };
+                    let inserted: Post = diesel::insert_into(p::posts)
+                        .values(new_post)
+                        .returning(POST_COLUMNS)
+                        .get_result(c)?;
+                    save_revisions(c, Some(NewRevision::of_post(&inserted, finger_print)))?;
+                    save_rendered_post(c, &inserted)?;
+                    created.push((inserted.id, inserted.title));
+                }
+                ImportReport { created, conflicts }
+            }
+            PostPublish { id, publish_at } => {
+                use crate::schema::posts::dsl as p;
+                let time = Utc::now().naive_local();
+                let (public_date, status) = publish_state(time, publish_at);
+                match diesel::update(p::posts.filter(p::id.eq(id)))
+                    .set((p::public_date.eq(public_date), p::status.eq(status), p::update_date.eq(time)))
+                    .execute(c)? {
+                    0 => Error(format!("no such post {}", id)),
+                    n => Success(n)
+                }
             }
             PostComments(post_id) => {
-                use crate::schema::comments::dsl as c;
-                c::comments.filter(c::post_id.eq(post_id))
-                    .load_async(conn)
-                    .await
-                    .map(|x| CommentList(x))
+                use crate::schema::comments::dsl as m;
+                CommentList(m::comments.filter(m::post_id.eq(post_id))
+                    .order_by((m::created_at, m::id))
+                    .load(c)?)
+            }
+            ContentDigests => {
+                use crate::schema::posts::dsl as p;
+                use crate::schema::pages::dsl as g;
+                let posts: Vec<Post> = p::posts
+                    .select(POST_COLUMNS)
+                    .order_by(p::id)
+                    .load(c)?;
+                let pages: Vec<Page> = g::pages
+                    .order_by(g::id)
+                    .load(c)?;
+                posts.iter()
+                    .map(ContentDigest::of_post)
+                    .chain(pages.iter().map(ContentDigest::of_page))
+                    .collect::<anyhow::Result<Vec<_>>>()
+                    .map(|x| ContentDigestList(x))
                     .unwrap_or_else(Into::into)
             }
             ListOperation { list_type } => {
                 match list_type {
                     ModelType::Comment => {
-                        use crate::schema::comments::dsl as c;
-                        c::comments.load_async(conn)
-                            .await
-                            .map(|x| CommentList(x))
-                            .unwrap_or_else(Into::into)
+                        use crate::schema::comments::dsl as m;
+                        CommentList(m::comments
+                            .order_by((m::created_at, m::id))
+                            .load(c)?)
                     }
                     ModelType::Post => {
                         use crate::schema::posts::dsl as p;
-                        p::posts
-                            .select((p::id, p::title, p::public_date, p::update_date))
-                            .load_async(conn)
-                            .await
-                            .map(|x| PostList(x))
-                            .unwrap_or_else(Into::into)
+                        PostList(p::posts
+                            .select((p::id, p::title, p::slug, p::public_date, p::update_date, p::status))
+                            .load(c)?)
                     }
                     ModelType::Page => {
                         use crate::schema::pages::dsl as p;
-                        p::pages
-                            .select((p::id, p::title))
-                            .load_async(conn)
-                            .await
-                            .map(|x| PageList(x))
-                            .unwrap_or_else(Into::into)
+                        PageList(p::pages
+                            .select((p::id, p::title, p::slug))
+                            .load(c)?)
                     }
                 }
             }
             CheckOperation { id, check_type } => {
                 match check_type {
                     ModelType::Comment => {
-                        use crate::schema::comments::dsl as c;
-                        c::comments
-                            .filter(c::id.eq(id))
-                            .first_async(conn)
-                            .await
-                            .map(|x| CommentInfo(x))
-                            .unwrap_or_else(Into::into)
+                        use crate::schema::comments::dsl as m;
+                        CommentInfo(m::comments
+                            .filter(m::id.eq(id))
+                            .first(c)?)
                     }
                     ModelType::Post => {
                         use crate::schema::posts::dsl as p;
-                        p::posts
+                        PostInfo(p::posts
                             .select(POST_COLUMNS)
                             .filter(p::id.eq(id))
-                            .first_async(conn)
-                            .await
-                            .map(|x| PostInfo(x))
-                            .unwrap_or_else(Into::into)
+                            .first(c)?)
                     }
                     ModelType::Page => {
                         use crate::schema::pages::dsl as p;
-                        p::pages
+                        PageInfo(p::pages
                             .filter(p::id.eq(id))
-                            .first_async(conn)
-                            .await
-                            .map(|x| PageInfo(x))
-                            .unwrap_or_else(Into::into)
+                            .first(c)?)
                     }
                 }
             }
             DeleteOperation { id, delete_type } => {
                 match delete_type {
                     ModelType::Comment => {
-                        use crate::schema::comments::dsl as c;
-                        diesel::delete(c::comments
-                            .filter(c::id.eq(id)))
-                            .execute_async(conn)
-                            .await
-                            .map(|s| Success(s))
-                            .unwrap_or_else(Into::into)
+                        use crate::schema::comments::dsl as m;
+                        Success(diesel::delete(m::comments
+                            .filter(m::id.eq(id)))
+                            .execute(c)?)
                     }
                     ModelType::Post => {
                         use crate::schema::posts::dsl as p;
-                        diesel::delete(p::posts
+                        forget_history(c, "post", id)?;
+                        forget_rendered(c, "post", id)?;
+                        Success(diesel::delete(p::posts
                             .filter(p::id.eq(id)))
-                            .execute_async(conn)
-                            .await
-                            .map(|s| Success(s))
-                            .unwrap_or_else(Into::into)
+                            .execute(c)?)
                     }
                     ModelType::Page => {
                         use crate::schema::pages::dsl as p;
-                        diesel::delete(p::pages
+                        forget_history(c, "page", id)?;
+                        forget_rendered(c, "page", id)?;
+                        Success(diesel::delete(p::pages
                             .filter(p::id.eq(id)))
-                            .execute_async(conn)
-                            .await
-                            .map(|s| Success(s))
-                            .unwrap_or_else(Into::into)
+                            .execute(c)?)
                     }
                 }
             }
-        }
+            ListPendingComments => {
+                use crate::schema::comments::dsl as m;
+                CommentList(m::comments
+                    .filter(m::approved.eq(false))
+                    .order_by((m::created_at, m::id))
+                    .load(c)?)
+            }
+            ApproveComment(id) => {
+                use crate::schema::comments::dsl as m;
+                use crate::schema::approved_finger_prints::dsl as f;
+                let approved: Vec<(String, bool)> = diesel::update(m::comments.filter(m::id.eq(id)))
+                    .set(m::approved.eq(true))
+                    .returning((m::finger_print, m::signed))
+                    .get_results(c)?;
+                // unsigned comments carry no identity that could be trusted later
+                for (i, _) in approved.iter().filter(|x| x.1) {
+                    diesel::insert_into(f::approved_finger_prints)
+                        .values(f::finger_print.eq(i.as_str()))
+                        .on_conflict_do_nothing()
+                        .execute(c)?;
+                }
+                Success(approved.len())
+            }
+            RejectComment(id) => {
+                use crate::schema::comments::dsl as m;
+                Success(diesel::delete(m::comments
+                    .filter(m::id.eq(id))
+                    .filter(m::approved.eq(false)))
+                    .execute(c)?)
+            }
+            AddCommenterKey(armored_key) => {
+                use crate::schema::commenter_keys::dsl as k;
+                match crate::keyring::parse_public_key(armored_key.as_str()) {
+                    Ok(key) => {
+                        let count = diesel::insert_into(k::commenter_keys)
+                            .values(&key)
+                            .execute(c)?;
+                        adopt_legacy_finger_print(c, &key)?;
+                        Success(count)
+                    }
+                    Err(e) => e.into()
+                }
+            }
+            ListCommenterKeys => {
+                use crate::schema::commenter_keys::dsl as k;
+                CommenterKeyList(k::commenter_keys
+                    .select((k::finger_print, k::user_id, k::created_at))
+                    .order_by(k::created_at)
+                    .load(c)?)
+            }
+            RemoveCommenterKey(finger_print) => {
+                use crate::schema::commenter_keys::dsl as k;
+                Success(diesel::delete(k::commenter_keys
+                    .filter(k::finger_print.eq(crate::keyring::normalize_finger_print(&finger_print))))
+                    .execute(c)?)
+            }
+            AddAdminKey { name, role, public_key } => {
+                use crate::schema::admin_keys::dsl as k;
+                let finger_print = match crate::crypto::PublicKey::load(public_key.as_str())
+                    .and_then(|x| x.fingerprint()) {
+                    Ok(finger_print) => finger_print,
+                    Err(e) => return Ok(e.into())
+                };
+                Success(diesel::insert_into(k::admin_keys)
+                    .values(NewAdminKey {
+                        name,
+                        role,
+                        finger_print,
+                        public_key,
+                    })
+                    .execute(c)?)
+            }
+            ListAdminKeys => {
+                use crate::schema::admin_keys::dsl as k;
+                AdminKeyList(k::admin_keys
+                    .select((k::id, k::name, k::role, k::finger_print, k::created_at, k::revoked_at))
+                    .order_by(k::id)
+                    .load(c)?)
+            }
+            RevokeAdminKey(name) => {
+                use crate::schema::admin_keys::dsl as k;
+                Success(diesel::update(k::admin_keys
+                    .filter(k::name.eq(name))
+                    .filter(k::revoked_at.is_null()))
+                    .set(k::revoked_at.eq(Utc::now().naive_local()))
+                    .execute(c)?)
+            }
+            AuditLog { since, limit } => {
+                use crate::schema::audit_logs::dsl as a;
+                AuditLogList(a::audit_logs
+                    .filter(a::created_at.ge(since.unwrap_or(chrono::NaiveDateTime::from_timestamp(0, 0))))
+                    .order_by(a::id.desc())
+                    .limit(limit.unwrap_or(AUDIT_LIMIT).max(1).min(MAX_AUDIT_LIMIT))
+                    .load(c)?)
+            }
+            ExportSite => {
+                use crate::schema::posts::dsl as p;
+                use crate::schema::pages::dsl as g;
+                use crate::schema::comments::dsl as m;
+                SiteExport(SiteArchive {
+                    posts: p::posts.select(POST_COLUMNS).order_by(p::id).load(c)?,
+                    pages: g::pages.order_by(g::id).load(c)?,
+                    comments: m::comments.order_by(m::id).load(c)?,
+                })
+            }
+            ImportSite(mut archive) => {
+                use crate::schema::posts::dsl as p;
+                use crate::schema::pages::dsl as g;
+                use crate::schema::comments::dsl as m;
+                // the archive keeps its ids, which would collide with anything already here
+                if !site_is_empty(c)? {
+                    return Ok(Error("the site already has posts, pages or comments, \
+                                     an archive can only be imported into an empty site".to_string()));
+                }
+                // replies reference earlier comments
+                archive.comments.sort_by_key(|x| x.id);
+                let mut count = 0;
+                for chunk in archive.posts.chunks(IMPORT_CHUNK) {
+                    count += diesel::insert_into(p::posts).values(chunk).execute(c)?;
+                }
+                for chunk in archive.pages.chunks(IMPORT_CHUNK) {
+                    count += diesel::insert_into(g::pages).values(chunk).execute(c)?;
+                }
+                for chunk in archive.comments.chunks(IMPORT_CHUNK) {
+                    count += diesel::insert_into(m::comments).values(chunk).execute(c)?;
+                }
+                save_revisions(c, archive.posts.iter()
+                    .map(|x| NewRevision::of_post(x, finger_print))
+                    .chain(archive.pages.iter()
+                        .map(|x| NewRevision::of_page(x, finger_print))))?;
+                for post in archive.posts.iter() {
+                    save_rendered_post(c, post)?;
+                }
+                for page in archive.pages.iter() {
+                    save_rendered_page(c, page)?;
+                }
+                // rows came with their ids, move the sequences past them
+                for table in &["posts", "pages", "comments"] {
+                    diesel::sql_query(format!(
+                        "SELECT setval('{0}_id_seq', COALESCE((SELECT MAX(id) FROM {0}), 0) + 1, false)",
+                        table))
+                        .execute(c)?;
+                }
+                Success(count)
+            }
+            ListRevisions { id, revision_type } => {
+                use crate::schema::revisions::dsl as r;
+                let model_type = match revision_type {
+                    ModelType::Post => "post",
+                    ModelType::Page => "page",
+                    ModelType::Comment => return Ok(Error("comments have no revisions".to_string()))
+                };
+                RevisionList(r::revisions
+                    .select((r::id, r::title, r::created_at, r::finger_print))
+                    .filter(r::model_type.eq(model_type))
+                    .filter(r::model_id.eq(id))
+                    .order_by(r::id)
+                    .load(c)?)
+            }
+            CheckRevision(id) => {
+                use crate::schema::revisions::dsl as r;
+                RevisionInfo(r::revisions
+                    .filter(r::id.eq(id))
+                    .first(c)?)
+            }
+            RestoreRevision(id) => {
+                use crate::schema::revisions::dsl as r;
+                let time = Utc::now().naive_local();
+                let revision: Revision = r::revisions
+                    .filter(r::id.eq(id))
+                    .first(c)?;
+                if revision.model_type == "post" {
+                    use crate::schema::posts::dsl as p;
+                    let updated: Vec<Post> = diesel::update(p::posts.filter(p::id.eq(revision.model_id)))
+                        .set(NewPostRaw {
+                            title: Some(revision.title),
+                            public_date: None,
+                            update_date: Some(time),
+                            tags: Some(revision.tags),
+                            content: Some(revision.content),
+                            status: None,
+                            slug: None,
+                            toc: None,
+                        })
+                        .returning(POST_COLUMNS)
+                        .get_results(c)?;
+                    for post in updated.iter() {
+                        save_rendered_post(c, post)?;
+                    }
+                    Success(save_revisions(c, updated.iter()
+                        .map(|x| NewRevision::of_post(x, finger_print)))?)
+                } else {
+                    use crate::schema::pages::dsl as p;
+                    let updated: Vec<Page> = diesel::update(p::pages.filter(p::id.eq(revision.model_id)))
+                        .set(NewPageRaw {
+                            title: Some(revision.title),
+                            content: Some(revision.content),
+                            important: None,
+                            description: None,
+                            slug: None,
+                        })
+                        .get_results(c)?;
+                    for page in updated.iter() {
+                        save_rendered_page(c, page)?;
+                    }
+                    Success(save_revisions(c, updated.iter()
+                        .map(|x| NewRevision::of_page(x, finger_print)))?)
+                }
+            }
+            Preview { kind, title, content, tags } => {
+                let time = Utc::now().naive_local();
+                let slug = slugify(&title);
+                match kind {
+                    ModelType::Post => {
+                        let post = Post {
+                            id: 0,
+                            title,
+                            public_date: time,
+                            update_date: time,
+                            tags: normalize_tags(tags),
+                            content,
+                            status: PostStatus::Draft,
+                            slug,
+                            toc: true,
+                        };
+                        let (content, table_of_contents) = post.render_with_contents();
+                        PreviewResult {
+                            content,
+                            abstract_content: Some(post.render_abstract(FEED_ABSTRACT)),
+                            table_of_contents,
+                        }
+                    }
+                    ModelType::Page => {
+                        let page = Page {
+                            id: 0,
+                            title,
+                            content,
+                            important: false,
+                            description: String::new(),
+                            slug,
+                        };
+                        PreviewResult {
+                            content: page.render_content(),
+                            abstract_content: None,
+                            table_of_contents: None,
+                        }
+                    }
+                    ModelType::Comment => Error("comments cannot be previewed".to_string())
+                }
+            }
+            RebuildCache => {
+                use crate::schema::posts::dsl as p;
+                use crate::schema::pages::dsl as g;
+                use crate::schema::rendered_html::dsl as h;
+                let posts: Vec<Post> = p::posts
+                    .select(POST_COLUMNS)
+                    .load(c)?;
+                let pages: Vec<Page> = g::pages.load(c)?;
+                diesel::delete(h::rendered_html).execute(c)?;
+                for post in posts.iter() {
+                    save_rendered_post(c, post)?;
+                }
+                for page in pages.iter() {
+                    save_rendered_page(c, page)?;
+                }
+                Success(posts.len() + pages.len())
+            }
+            Batch(requests) => {
+                let mut results = Vec::with_capacity(requests.len());
+                // a savepoint, so that a failed item takes back the items before it
+                let committed = c.transaction(|| {
+                    for request in requests {
+                        let result = match request {
+                            Batch(_) => Error("batches cannot be nested".to_string()),
+                            request => request.execute(c, finger_print).unwrap_or_else(Into::into)
+                        };
+                        let failed = matches!(result, Error(_));
+                        results.push(result);
+                        if failed {
+                            return Err(diesel::result::Error::RollbackTransaction);
+                        }
+                    }
+                    Ok(())
+                }).is_ok();
+                BatchResult { committed, results }
+            }
+        })
     }
 }
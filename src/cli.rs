@@ -2,7 +2,12 @@ use std::path::PathBuf;
 
 use crate::cli::client::SubCommand;
 
+pub mod archive;
 pub mod client;
+pub mod edit;
+pub mod preview;
+pub mod static_import;
+pub mod sync;
 
 #[derive(structopt::StructOpt)]
 pub enum Command {
@@ -26,6 +31,31 @@ pub enum Command {
         web_root: PathBuf,
         #[structopt(short = "u", long, help = "Server domain", env = "BLOG_SERVER_DOMAIN")]
         domain: String,
+        #[structopt(short, long, help = "Hold new comments for approval before showing them")]
+        moderation: bool,
+        #[structopt(short, long, help = "Let comments from previously approved fingerprints skip moderation")]
+        trust_approved: bool,
+        #[structopt(long, default_value = "16", help = "Leading zero bits required from unsigned comments")]
+        pow_difficulty: u32,
+        #[structopt(long, help = "Proxy whose X-Forwarded-For header is trusted, can be repeated")]
+        trusted_proxy: Vec<std::net::IpAddr>,
+        #[structopt(long, default_value = "5/60", help = "Comment submissions and removals per IP, as <requests>/<seconds>")]
+        comment_rate: crate::limit::Rate,
+        #[structopt(long, default_value = "30/60", help = "Searches per IP, as <requests>/<seconds>")]
+        search_rate: crate::limit::Rate,
+        #[structopt(long, default_value = "120/60", help = "API requests per IP, as <requests>/<seconds>")]
+        api_rate: crate::limit::Rate,
+        #[structopt(long, default_value = "20/3600", help = "Comments per signing key, as <requests>/<seconds>")]
+        finger_print_rate: crate::limit::Rate,
+        #[structopt(long, default_value = "60/60", help = "API requests per admin key, as <requests>/<seconds>")]
+        admin_key_rate: crate::limit::Rate,
+        #[structopt(long, default_value = "memory", help = "Where to remember API nonces: memory or postgres")]
+        nonce_store: crate::crypto::NonceStoreKind,
+        #[structopt(long, default_value = "InspiredGitHub", help = "Syntect theme giving the colours of highlighted code")]
+        highlight_theme: String,
+        #[structopt(long, default_value = "tables,strikethrough,tasklists",
+        help = "Markdown extensions of comments: tables, footnotes, strikethrough, tasklists or none")]
+        comment_extensions: crate::markdown::Extensions,
     },
     #[structopt(name = "client", about = "Use as a client")]
     Client {
@@ -52,5 +82,25 @@ pub enum Command {
         encryption: bool,
         #[structopt(short = "l", long, help = "The length of the RSA private key", default_value = "4096")]
         key_length: usize,
+        #[structopt(short, long, help = "Key algorithm: rsa or ed25519", default_value = "rsa")]
+        algorithm: crate::crypto::Algorithm,
+    },
+    #[structopt(name = "static-build", about = "Render the whole site into a directory for a static host")]
+    StaticBuild {
+        #[structopt(short = "d", long, help = "Postgres connection url", env = "BLOG_POSTGRES")]
+        postgres: String,
+        #[structopt(short, long, help = "Name of the blog", env = "BLOG_NAME", default_value = "Rusty Blog")]
+        blog_name: String,
+        #[structopt(short, long, help = "Root of the web server", env = "BLOG_WEB_ROOT", default_value = ".")]
+        web_root: PathBuf,
+        #[structopt(short = "u", long, help = "Server domain", env = "BLOG_SERVER_DOMAIN")]
+        domain: String,
+        #[structopt(short, long, help = "Directory to write the site into", default_value = "./public")]
+        output: PathBuf,
+        #[structopt(long, default_value = "InspiredGitHub", help = "Syntect theme giving the colours of highlighted code")]
+        highlight_theme: String,
+        #[structopt(long, default_value = "tables,strikethrough,tasklists",
+        help = "Markdown extensions of comments: tables, footnotes, strikethrough, tasklists or none")]
+        comment_extensions: crate::markdown::Extensions,
     },
 }
\ No newline at end of file
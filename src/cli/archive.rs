@@ -0,0 +1,146 @@
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::api::SiteArchive;
+use crate::model::{Comment, Page, Post, PostStatus};
+
+const FRONT_MATTER_FENCE: &str = "---";
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PostFrontMatter {
+    id: i32,
+    title: String,
+    slug: String,
+    tags: Vec<String>,
+    public_date: chrono::NaiveDateTime,
+    update_date: chrono::NaiveDateTime,
+    status: PostStatus,
+    #[serde(default = "crate::model::default_toc")]
+    toc: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PageFrontMatter {
+    id: i32,
+    title: String,
+    slug: String,
+    description: String,
+    important: bool,
+}
+
+/// Render a markdown document with a YAML front matter block.
+pub fn format_document<T: serde::Serialize>(front_matter: &T, content: &str) -> anyhow::Result<String> {
+    let yaml = serde_yaml::to_string(front_matter)?;
+    // serde_yaml starts documents with its own fence
+    let yaml = yaml.trim_start_matches(FRONT_MATTER_FENCE).trim();
+    Ok(format!("{fence}\n{}\n{fence}\n{}", yaml, content, fence = FRONT_MATTER_FENCE))
+}
+
+/// Split a document into its YAML front matter and the markdown after it.
+pub fn split_document(document: &str) -> Option<(&str, &str)> {
+    let start = document.find('\n')? + 1;
+    if document[..start].trim_end() != FRONT_MATTER_FENCE {
+        return None;
+    }
+    let mut offset = start;
+    while offset < document.len() {
+        let end = document[offset..].find('\n')
+            .map(|x| offset + x + 1)
+            .unwrap_or_else(|| document.len());
+        if document[offset..end].trim_end() == FRONT_MATTER_FENCE {
+            return Some((&document[start..offset], &document[end..]));
+        }
+        offset = end;
+    }
+    None
+}
+
+fn parse_document<T: serde::de::DeserializeOwned>(document: &str) -> anyhow::Result<(T, String)> {
+    let (front_matter, content) = split_document(document)
+        .ok_or_else(|| anyhow!("missing front matter"))?;
+    Ok((serde_yaml::from_str(front_matter)?, content.to_string()))
+}
+
+fn append<W: std::io::Write>(builder: &mut tar::Builder<W>, path: String, data: &[u8]) -> anyhow::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+/// Write `posts/<id>-<slug>.md`, `pages/<id>-<slug>.md` and `comments.json` into a tar file.
+pub fn write_archive(path: &Path, site: &SiteArchive) -> anyhow::Result<()> {
+    let mut builder = tar::Builder::new(std::fs::File::create(path)?);
+    for post in site.posts.iter() {
+        let document = format_document(&PostFrontMatter {
+            id: post.id,
+            title: post.title.clone(),
+            slug: post.slug.clone(),
+            tags: post.tags.clone(),
+            public_date: post.public_date,
+            update_date: post.update_date,
+            status: post.status,
+            toc: post.toc,
+        }, post.content.as_str())?;
+        append(&mut builder, format!("posts/{}-{}.md", post.id, post.slug), document.as_bytes())?;
+    }
+    for page in site.pages.iter() {
+        let document = format_document(&PageFrontMatter {
+            id: page.id,
+            title: page.title.clone(),
+            slug: page.slug.clone(),
+            description: page.description.clone(),
+            important: page.important,
+        }, page.content.as_str())?;
+        append(&mut builder, format!("pages/{}-{}.md", page.id, page.slug), document.as_bytes())?;
+    }
+    append(&mut builder, "comments.json".to_string(),
+           serde_json::to_string_pretty(&site.comments)?.as_bytes())?;
+    builder.finish()?;
+    Ok(())
+}
+
+/// Read an archive written by [`write_archive`].
+pub fn read_archive(path: &Path) -> anyhow::Result<SiteArchive> {
+    let mut archive = tar::Archive::new(std::fs::File::open(path)?);
+    let mut site = SiteArchive::default();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut text = String::new();
+        entry.read_to_string(&mut text)?;
+        let error = |e: anyhow::Error| anyhow!("{}: {}", name, e);
+        if name.starts_with("posts/") && name.ends_with(".md") {
+            let (front, content): (PostFrontMatter, String) = parse_document(text.as_str()).map_err(error)?;
+            site.posts.push(Post {
+                id: front.id,
+                title: front.title,
+                public_date: front.public_date,
+                update_date: front.update_date,
+                tags: front.tags,
+                content,
+                status: front.status,
+                slug: front.slug,
+                toc: front.toc,
+            });
+        } else if name.starts_with("pages/") && name.ends_with(".md") {
+            let (front, content): (PageFrontMatter, String) = parse_document(text.as_str()).map_err(error)?;
+            site.pages.push(Page {
+                id: front.id,
+                title: front.title,
+                content,
+                important: front.important,
+                description: front.description,
+                slug: front.slug,
+            });
+        } else if name == "comments.json" {
+            site.comments = serde_json::from_str::<Vec<Comment>>(text.as_str())
+                .map_err(|e| error(e.into()))?;
+        }
+    }
+    Ok(site)
+}
@@ -4,7 +4,11 @@ use std::str::FromStr;
 use anyhow::*;
 use structopt::*;
 use crate::utils::confirm;
-use crate::api::{JsonRequest, ModelType};
+use crate::api::{JsonRequest, JsonResponse, ModelType};
+use crate::crypto::Packet;
+use crate::KeyPair;
+use crate::model::{AdminRole, Revision};
+use crate::cli::static_import::StaticFormat;
 
 #[derive(Debug, StructOpt)]
 pub struct Config {
@@ -33,6 +37,23 @@ pub enum SubCommand {
         content_file: PathBuf,
         #[structopt(short = "g", long, help = "Post tags")]
         tags: TagList,
+        #[structopt(short, long, help = "Keep the post as a draft")]
+        draft: bool,
+        #[structopt(short = "a", long, help = "Publish time in RFC 3339, e.g. 2020-07-01T08:00:00+08:00",
+        parse(try_from_str = "parse_time"))]
+        publish_at: Option<chrono::NaiveDateTime>,
+        #[structopt(short, long, help = "Url slug of the post, derived from the title if not set")]
+        slug: Option<String>,
+        #[structopt(long, help = "Never show a table of contents above the post")]
+        no_toc: bool,
+    },
+    #[structopt(name = "publish-post", about = "Publish a draft post, now or at a given time")]
+    PublishPost {
+        #[structopt(short, long, help = "Id number of the post")]
+        id: i32,
+        #[structopt(short = "a", long, help = "Publish time in RFC 3339, e.g. 2020-07-01T08:00:00+08:00",
+        parse(try_from_str = "parse_time"))]
+        publish_at: Option<chrono::NaiveDateTime>,
     },
     #[structopt(name = "create-page", about = "Create a new page")]
     CreatePage {
@@ -44,6 +65,8 @@ pub enum SubCommand {
         description: String,
         #[structopt(short = "m", long, help = "Mark the page as an important one")]
         important: bool,
+        #[structopt(short, long, help = "Url slug of the page, derived from the title if not set")]
+        slug: Option<String>,
     },
     #[structopt(name = "update-post", about = "Update a post")]
     UpdatePost {
@@ -55,6 +78,10 @@ pub enum SubCommand {
         tags: Option<TagList>,
         #[structopt(short, long, help = "Post title")]
         title: Option<String>,
+        #[structopt(short, long, help = "New url slug, the old one keeps redirecting")]
+        slug: Option<String>,
+        #[structopt(long, help = "Whether a long post shows its table of contents")]
+        toc: Option<bool>,
     },
     #[structopt(name = "update-page", about = "Update a page")]
     UpdatePage {
@@ -68,6 +95,18 @@ pub enum SubCommand {
         description: Option<String>,
         #[structopt(short = "m", long, help = "Whether the page is an important one")]
         important: Option<bool>,
+        #[structopt(short, long, help = "New url slug, the old one keeps redirecting")]
+        slug: Option<String>,
+    },
+    #[structopt(name = "edit-post", about = "Edit a post in $EDITOR")]
+    EditPost {
+        #[structopt(short, long, help = "Id number of the post")]
+        id: i32,
+    },
+    #[structopt(name = "edit-page", about = "Edit a page in $EDITOR")]
+    EditPage {
+        #[structopt(short, long, help = "Id number of the page")]
+        id: i32,
     },
     #[structopt(name = "remove-post", about = "Remove a post")]
     RemovePost {
@@ -119,6 +158,160 @@ pub enum SubCommand {
         #[structopt(short, long, help = "Show raw content only")]
         raw: bool,
     },
+    #[structopt(name = "list-pending-comment", about = "List comments waiting for moderation")]
+    ListPendingComment,
+    #[structopt(name = "approve-comment", about = "Approve a pending comment")]
+    ApproveComment {
+        #[structopt(short, long, help = "Id number")]
+        id: i32
+    },
+    #[structopt(name = "reject-comment", about = "Reject and remove a pending comment")]
+    RejectComment {
+        #[structopt(short, long, help = "Id number")]
+        id: i32
+    },
+    #[structopt(name = "history-post", about = "List revisions of a post")]
+    HistoryPost {
+        #[structopt(short, long, help = "Id number of the post")]
+        id: i32
+    },
+    #[structopt(name = "diff-post", about = "Show the difference between two revisions")]
+    DiffPost {
+        #[structopt(short, long, help = "Id number of the old revision")]
+        from: i32,
+        #[structopt(short, long, help = "Id number of the new revision")]
+        to: i32,
+    },
+    #[structopt(name = "restore-post", about = "Restore a post to a revision")]
+    RestorePost {
+        #[structopt(short, long, help = "Id number of the revision")]
+        id: i32
+    },
+    #[structopt(name = "add-commenter-key", about = "Allow an armored public key to sign comments")]
+    AddCommenterKey {
+        #[structopt(short, long, help = "Path to the armored public key")]
+        key_file: PathBuf
+    },
+    #[structopt(name = "list-commenter-key", about = "List keys allowed to sign comments")]
+    ListCommenterKey,
+    #[structopt(name = "remove-commenter-key", about = "Remove a key from the comment keyring")]
+    RemoveCommenterKey {
+        #[structopt(short, long, help = "Fingerprint of the key")]
+        finger_print: String
+    },
+    #[structopt(name = "add-admin-key", about = "Authorize a public key to use the API")]
+    AddAdminKey {
+        #[structopt(short, long, help = "Name of the key holder")]
+        name: String,
+        #[structopt(short, long, help = "Role of the key: owner, editor or moderator")]
+        role: AdminRole,
+        #[structopt(short, long, help = "Path to the public key")]
+        key_file: PathBuf,
+    },
+    #[structopt(name = "list-admin-key", about = "List keys authorized to use the API")]
+    ListAdminKey,
+    #[structopt(name = "revoke-admin-key", about = "Revoke an authorized key")]
+    RevokeAdminKey {
+        #[structopt(short, long, help = "Name of the key holder")]
+        name: String
+    },
+    #[structopt(name = "audit", about = "Show recent admin operations")]
+    Audit {
+        #[structopt(short, long, help = "Only show operations after this time in RFC 3339",
+        parse(try_from_str = "parse_time"))]
+        since: Option<chrono::NaiveDateTime>,
+        #[structopt(short, long, help = "Maximum number of entries, 100 by default")]
+        limit: Option<i64>,
+    },
+    #[structopt(name = "export", about = "Export posts, pages and comments into a tar archive")]
+    Export {
+        #[structopt(help = "Path of the archive to write")]
+        file: PathBuf
+    },
+    #[structopt(name = "rebuild-cache", about = "Render every post and page again after the renderer has changed")]
+    RebuildCache,
+    #[structopt(name = "import", about = "Import an exported archive into an empty site")]
+    Import {
+        #[structopt(help = "Path of the archive to read")]
+        file: PathBuf
+    },
+    #[structopt(name = "import-static", about = "Import posts from a hugo, jekyll or zola content directory")]
+    ImportStatic {
+        #[structopt(help = "Directory holding the markdown posts")]
+        dir: PathBuf,
+        #[structopt(short, long, help = "Format of the directory: hugo, jekyll or zola")]
+        format: StaticFormat,
+    },
+    #[structopt(name = "sync", about = "Make the server match a directory holding posts/ and pages/")]
+    Sync {
+        #[structopt(help = "Directory with markdown files carrying front matter")]
+        dir: PathBuf,
+        #[structopt(long, help = "Only show what would change")]
+        dry_run: bool,
+    },
+    #[structopt(name = "preview", about = "Render a post or page on the server without publishing it")]
+    Preview {
+        #[structopt(short, long, help = "Path to the content file, front matter is optional")]
+        content_file: PathBuf,
+        #[structopt(short, long, help = "Title, taken from the front matter or the file name if not set")]
+        title: Option<String>,
+        #[structopt(short = "g", long, help = "Post tags, taken from the front matter if not set")]
+        tags: Option<TagList>,
+        #[structopt(short, long, help = "Render as a page instead of a post")]
+        page: bool,
+        #[structopt(short, long, help = "Path of the HTML file to write", default_value = "preview.html")]
+        output: PathBuf,
+        #[structopt(long, help = "Serve the preview on this local port and reload it when the file changes")]
+        serve: Option<u16>,
+    },
+}
+
+pub struct Remote {
+    pub address: String,
+    pub key_pair: KeyPair,
+}
+
+impl Remote {
+    pub async fn send(&self, request: JsonRequest) -> anyhow::Result<JsonResponse> {
+        let packet = Packet::from_json_request(request, &self.key_pair.server_private, &self.key_pair.owner_public).await?;
+        let mut response = surf::Client::new()
+            .post(self.address.as_str())
+            .body(simd_json::to_string(&packet)?)
+            .await
+            .map_err(|x| anyhow!("{}", x))?;
+        // a rejected request carries an error page instead of a packet
+        if response.status() == http_types::StatusCode::TooManyRequests {
+            return match response.header("Retry-After") {
+                Some(wait) => Err(anyhow!("rate limited, retry after {} s", wait.last().as_str())),
+                None => Err(anyhow!("rate limited"))
+            };
+        }
+        if !response.status().is_success() {
+            return Err(anyhow!("server replied {}", response.status()));
+        }
+        let response = response
+            .body_string()
+            .await
+            .map_err(|x| anyhow!("{}", x))
+            .and_then(|mut x| simd_json::from_str::<Packet>(x.as_mut_str())
+                .map_err(|x| x.into()))?;
+        response
+            .to_json_request(&self.key_pair.server_private, &self.key_pair.owner_public, None)
+            .await
+    }
+
+    /// The blog itself, where the stylesheets are served from.
+    pub fn site_url(&self) -> &str {
+        self.address.trim_end_matches("/api")
+    }
+
+    pub async fn fetch_revision(&self, id: i32) -> anyhow::Result<Revision> {
+        match self.send(JsonRequest::CheckRevision(id)).await? {
+            JsonResponse::RevisionInfo(revision) => Ok(revision),
+            JsonResponse::Error(e) => Err(anyhow!("{}", e)),
+            _ => Err(anyhow!("unexpected server reply"))
+        }
+    }
 }
 
 impl FromStr for TagList {
@@ -135,25 +328,38 @@ impl FromStr for TagList {
     }
 }
 
+fn parse_time(s: &str) -> Result<chrono::NaiveDateTime, chrono::ParseError> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|x| x.naive_utc())
+}
+
 impl SubCommand {
     pub fn into_json_request(self) -> anyhow::Result<JsonRequest> {
         Ok(match self {
-            SubCommand::CreatePost { title, content_file, tags } => {
+            SubCommand::CreatePost { title, content_file, tags, draft, publish_at, slug, no_toc } => {
                 JsonRequest::PostCreate {
                     title,
                     content: std::fs::read_to_string(content_file.as_path())?,
                     tag: tags.0,
+                    draft,
+                    publish_at,
+                    slug,
+                    toc: if no_toc { Some(false) } else { None },
                 }
             }
-            SubCommand::CreatePage { title, content_file, important, description } => {
+            SubCommand::PublishPost { id, publish_at } => {
+                JsonRequest::PostPublish { id, publish_at }
+            }
+            SubCommand::CreatePage { title, content_file, important, description, slug } => {
                 JsonRequest::PageCreate {
                     title,
                     content: std::fs::read_to_string(content_file.as_path())?,
                     important,
-                    description
+                    description,
+                    slug,
                 }
             }
-            SubCommand::UpdatePost { id, content_file, tags, title } => {
+            SubCommand::UpdatePost { id, content_file, tags, title, slug, toc } => {
                 let content = if content_file.is_none() { None } else {
                     Some(std::fs::read_to_string(content_file.unwrap().as_path())?)
                 };
@@ -162,9 +368,13 @@ impl SubCommand {
                     title,
                     tags: tags.map(|x| x.0),
                     content,
+                    slug,
+                    if_unmodified_since: None,
+                    toc,
+                    draft: None,
                 }
             }
-            SubCommand::UpdatePage { id, title, content_file, important, description } => {
+            SubCommand::UpdatePage { id, title, content_file, important, description, slug } => {
                 let content = if content_file.is_none() { None } else {
                     Some(std::fs::read_to_string(content_file.unwrap().as_path())?)
                 };
@@ -173,7 +383,9 @@ impl SubCommand {
                     title,
                     content,
                     important,
-                    description
+                    description,
+                    slug,
+                    expected_digest: None,
                 }
             }
             SubCommand::RemovePost { id } => {
@@ -227,9 +439,164 @@ impl SubCommand {
                     check_type: ModelType::Page,
                 }
             }
+            SubCommand::ListPendingComment => {
+                JsonRequest::ListPendingComments
+            }
+            SubCommand::ApproveComment { id } => {
+                JsonRequest::ApproveComment(id)
+            }
+            SubCommand::RejectComment { id } => {
+                confirm(format!("reject comment {}", id))?;
+                JsonRequest::RejectComment(id)
+            }
+            SubCommand::HistoryPost { id } => {
+                JsonRequest::ListRevisions {
+                    id,
+                    revision_type: ModelType::Post,
+                }
+            }
+            SubCommand::RestorePost { id } => {
+                confirm(format!("restore revision {}", id))?;
+                JsonRequest::RestoreRevision(id)
+            }
+            SubCommand::AddCommenterKey { key_file } => {
+                JsonRequest::AddCommenterKey(std::fs::read_to_string(key_file.as_path())?)
+            }
+            SubCommand::ListCommenterKey => {
+                JsonRequest::ListCommenterKeys
+            }
+            SubCommand::AddAdminKey { name, role, key_file } => {
+                JsonRequest::AddAdminKey {
+                    name,
+                    role,
+                    public_key: std::fs::read_to_string(key_file.as_path())?,
+                }
+            }
+            SubCommand::ListAdminKey => {
+                JsonRequest::ListAdminKeys
+            }
+            SubCommand::RevokeAdminKey { name } => {
+                confirm(format!("revoke the key of {}", name))?;
+                JsonRequest::RevokeAdminKey(name)
+            }
+            SubCommand::Import { file } => {
+                let site = crate::cli::archive::read_archive(file.as_path())?;
+                confirm(format!("import {} posts, {} pages and {} comments",
+                                site.posts.len(), site.pages.len(), site.comments.len()))?;
+                JsonRequest::ImportSite(site)
+            }
+            SubCommand::Export { .. } => {
+                return Err(anyhow!("export cannot be sent as a single request"));
+            }
+            SubCommand::Audit { since, limit } => {
+                JsonRequest::AuditLog { since, limit }
+            }
+            SubCommand::RebuildCache => {
+                JsonRequest::RebuildCache
+            }
+            SubCommand::RemoveCommenterKey { finger_print } => {
+                confirm(format!("remove key {}", finger_print))?;
+                JsonRequest::RemoveCommenterKey(finger_print)
+            }
+            SubCommand::DiffPost { .. } => {
+                return Err(anyhow!("diff-post cannot be sent as a single request"));
+            }
+            SubCommand::ImportStatic { .. } => {
+                return Err(anyhow!("import-static cannot be sent as a single request"));
+            }
+            SubCommand::Sync { .. } => {
+                return Err(anyhow!("sync cannot be sent as a single request"));
+            }
+            SubCommand::Preview { .. } => {
+                return Err(anyhow!("preview cannot be sent as a single request"));
+            }
+            SubCommand::EditPost { .. } | SubCommand::EditPage { .. } => {
+                return Err(anyhow!("editing cannot be sent as a single request"));
+            }
         })
     }
 
+    pub async fn run(self, remote: &Remote) -> anyhow::Result<()> {
+        match self {
+            SubCommand::DiffPost { from, to } => {
+                let old = remote.fetch_revision(from).await?.to_document();
+                let new = remote.fetch_revision(to).await?.to_document();
+                let diff = similar::TextDiff::from_lines(old.as_str(), new.as_str());
+                print!("{}", diff.unified_diff()
+                    .context_radius(3)
+                    .header(format!("revision {}", from).as_str(),
+                            format!("revision {}", to).as_str()));
+            }
+            SubCommand::Export { file } => {
+                match remote.send(JsonRequest::ExportSite).await? {
+                    JsonResponse::SiteExport(site) => {
+                        crate::cli::archive::write_archive(file.as_path(), &site)?;
+                        println!("exported {} posts, {} pages and {} comments to {:?}",
+                                 site.posts.len(), site.pages.len(), site.comments.len(), file);
+                    }
+                    JsonResponse::Error(e) => return Err(anyhow!("{}", e)),
+                    _ => return Err(anyhow!("unexpected server reply"))
+                }
+            }
+            SubCommand::ImportStatic { dir, format } => {
+                let (converted, failed) = crate::cli::static_import::convert_dir(dir.as_path(), format)?;
+                for (path, error) in failed.iter() {
+                    eprintln!("skipped {:?}: {}", path, error);
+                }
+                for file in converted.iter() {
+                    for warning in file.warnings.iter() {
+                        eprintln!("{:?}: {}", file.path, warning);
+                    }
+                }
+                if converted.is_empty() {
+                    return Err(anyhow!("no post to import"));
+                }
+                confirm(format!("import {} posts", converted.len()))?;
+                let posts = converted.into_iter().map(|x| x.post).collect();
+                match remote.send(JsonRequest::PostImport(posts)).await? {
+                    JsonResponse::ImportReport { created, conflicts } => {
+                        for (id, title) in created.iter() {
+                            println!("created {}: {}", id, title);
+                        }
+                        for title in conflicts.iter() {
+                            println!("conflict: {} already exists", title);
+                        }
+                        println!("imported {} posts, {} conflicts", created.len(), conflicts.len());
+                    }
+                    JsonResponse::Error(e) => return Err(anyhow!("{}", e)),
+                    _ => return Err(anyhow!("unexpected server reply"))
+                }
+            }
+            SubCommand::Sync { dir, dry_run } => {
+                crate::cli::sync::sync(remote, dir.as_path(), dry_run).await?;
+            }
+            SubCommand::Preview { content_file, title, tags, page, output, serve } => {
+                let preview = crate::cli::preview::Preview {
+                    file: content_file,
+                    kind: if page { ModelType::Page } else { ModelType::Post },
+                    title,
+                    tags: tags.map(|x| x.0),
+                };
+                match serve {
+                    Some(port) => preview.serve(remote, port).await?,
+                    None => preview.write(remote, output.as_path()).await?,
+                }
+            }
+            SubCommand::EditPost { id } => crate::cli::edit::edit_post(remote, id).await?,
+            SubCommand::EditPage { id } => crate::cli::edit::edit_page(remote, id).await?,
+            command => {
+                let is_raw = command.is_raw_content();
+                let response = remote.send(command.into_json_request()?).await?;
+                if !is_raw {
+                    crate::utils::to_table(&response)?.printstd();
+                } else {
+                    println!("{}", response.get_raw_content()?);
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn is_raw_content(&self) -> bool {
         match self {
             SubCommand::CheckPost { raw, .. } | SubCommand::CheckComment { raw, .. } | SubCommand::CheckPage { raw, .. }
@@ -0,0 +1,168 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use tempfile::TempPath;
+
+use crate::api::{ContentDigest, JsonRequest, JsonResponse, ModelType};
+use crate::cli::archive::{format_document, split_document};
+use crate::cli::client::Remote;
+use crate::model::{Page, Post};
+use crate::utils::{confirm, normalize_tags};
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PostFrontMatter {
+    title: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default = "crate::model::default_toc")]
+    toc: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PageFrontMatter {
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    important: bool,
+}
+
+/// Open `path` in `$EDITOR`, which may carry its own arguments like `code --wait`.
+fn run_editor(path: &Path) -> anyhow::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| anyhow!("$EDITOR is empty"))?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+    Ok(())
+}
+
+/// Let the user edit `original` in a fresh temporary file and return the result, or `None`
+/// when nothing was changed.
+fn edit_document(prefix: &str, original: &str) -> anyhow::Result<Option<(TempPath, String)>> {
+    let path = tempfile::Builder::new()
+        .prefix(prefix)
+        .suffix(".md")
+        .tempfile()?
+        .into_temp_path();
+    std::fs::write(&path, original)?;
+    run_editor(&path)?;
+    let edited = std::fs::read_to_string(&path)?;
+    if edited == original {
+        path.close()?;
+        return Ok(None);
+    }
+    let diff = similar::TextDiff::from_lines(original, edited.as_str());
+    print!("{}", diff.unified_diff()
+        .context_radius(3)
+        .header("server", format!("{:?}", path).as_str()));
+    Ok(Some((path, edited)))
+}
+
+/// Remove the edited file once the update went through; otherwise keep it, so that the next
+/// edit does not lose it.
+fn finish_edit(path: TempPath, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    match result {
+        Ok(()) => {
+            path.close()?;
+            Ok(())
+        }
+        Err(e) => Err(anyhow!("{}, the edit is kept in {:?}", e, path.keep()?)),
+    }
+}
+
+fn parse_front_matter<T: serde::de::DeserializeOwned>(document: &str) -> anyhow::Result<(T, String)> {
+    let (front, content) = split_document(document)
+        .ok_or_else(|| anyhow!("front matter is missing"))?;
+    Ok((serde_yaml::from_str(front)?, content.to_string()))
+}
+
+async fn send_update(remote: &Remote, request: JsonRequest) -> anyhow::Result<()> {
+    match remote.send(request).await? {
+        JsonResponse::Success(_) => {
+            println!("updated");
+            Ok(())
+        }
+        JsonResponse::Error(e) => Err(anyhow!("{}", e)),
+        _ => Err(anyhow!("unexpected server reply"))
+    }
+}
+
+async fn update_post(remote: &Remote, post: &Post, edited: &str) -> anyhow::Result<()> {
+    let (front, content): (PostFrontMatter, String) = parse_front_matter(edited)?;
+    let tags = normalize_tags(front.tags);
+    confirm(format!("update post {}", post.id))?;
+    send_update(remote, JsonRequest::PostUpdate {
+        id: post.id,
+        title: Some(front.title).filter(|x| *x != post.title),
+        tags: Some(tags).filter(|x| *x != post.tags),
+        content: Some(content).filter(|x| *x != post.content),
+        slug: None,
+        if_unmodified_since: Some(post.update_date),
+        toc: Some(front.toc).filter(|x| *x != post.toc),
+        draft: None,
+    }).await
+}
+
+async fn update_page(remote: &Remote, page: &Page, edited: &str) -> anyhow::Result<()> {
+    let (front, content): (PageFrontMatter, String) = parse_front_matter(edited)?;
+    confirm(format!("update page {}", page.id))?;
+    send_update(remote, JsonRequest::PageUpdate {
+        id: page.id,
+        title: Some(front.title).filter(|x| *x != page.title),
+        content: Some(content).filter(|x| *x != page.content),
+        important: Some(front.important).filter(|x| *x != page.important),
+        description: Some(front.description).filter(|x| *x != page.description),
+        slug: None,
+        expected_digest: Some(ContentDigest::of_page(page)?.digest),
+    }).await
+}
+
+pub async fn edit_post(remote: &Remote, id: i32) -> anyhow::Result<()> {
+    let post = match remote.send(JsonRequest::CheckOperation { id, check_type: ModelType::Post }).await? {
+        JsonResponse::PostInfo(post) => post,
+        JsonResponse::Error(e) => return Err(anyhow!("{}", e)),
+        _ => return Err(anyhow!("unexpected server reply"))
+    };
+    let original = format_document(&PostFrontMatter {
+        title: post.title.clone(),
+        tags: post.tags.clone(),
+        toc: post.toc,
+    }, post.content.as_str())?;
+    let (path, edited) = match edit_document(format!("blog-post-{}-", id).as_str(), original.as_str())? {
+        Some(x) => x,
+        None => {
+            println!("nothing changed");
+            return Ok(());
+        }
+    };
+    finish_edit(path, update_post(remote, &post, edited.as_str()).await)
+}
+
+pub async fn edit_page(remote: &Remote, id: i32) -> anyhow::Result<()> {
+    let page = match remote.send(JsonRequest::CheckOperation { id, check_type: ModelType::Page }).await? {
+        JsonResponse::PageInfo(page) => page,
+        JsonResponse::Error(e) => return Err(anyhow!("{}", e)),
+        _ => return Err(anyhow!("unexpected server reply"))
+    };
+    let original = format_document(&PageFrontMatter {
+        title: page.title.clone(),
+        description: page.description.clone(),
+        important: page.important,
+    }, page.content.as_str())?;
+    let (path, edited) = match edit_document(format!("blog-page-{}-", id).as_str(), original.as_str())? {
+        Some(x) => x,
+        None => {
+            println!("nothing changed");
+            return Ok(());
+        }
+    };
+    finish_edit(path, update_page(remote, &page, edited.as_str()).await)
+}
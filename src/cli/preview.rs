@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use askama::Template;
+
+use crate::api::{JsonRequest, JsonResponse, ModelType};
+use crate::cli::client::Remote;
+use crate::template::PreviewTemplate;
+
+/// Optional front matter of a previewed file; values given on the command line win.
+#[derive(serde::Deserialize, Default)]
+struct PreviewFrontMatter {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+pub struct Preview {
+    pub file: PathBuf,
+    pub kind: ModelType,
+    pub title: Option<String>,
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Clone)]
+struct LiveState {
+    version: Arc<AtomicU64>,
+    page: Arc<RwLock<String>>,
+}
+
+async fn serve_page(request: tide::Request<LiveState>) -> tide::Result<tide::Response> {
+    let page = request.state().page.read().unwrap().clone();
+    Ok(crate::server::normal_page(page))
+}
+
+async fn serve_version(request: tide::Request<LiveState>) -> tide::Result<String> {
+    Ok(request.state().version.load(Ordering::SeqCst).to_string())
+}
+
+impl Preview {
+    fn read(&self) -> anyhow::Result<(String, Vec<String>, String)> {
+        let document = std::fs::read_to_string(self.file.as_path())?;
+        let (front, content) = match crate::cli::archive::split_document(document.as_str()) {
+            Some((front, content)) => (serde_yaml::from_str(front)?, content.to_string()),
+            None => (PreviewFrontMatter::default(), document.clone()),
+        };
+        let title = self.title.clone()
+            .or(front.title)
+            .or_else(|| self.file.file_stem().map(|x| x.to_string_lossy().to_string()))
+            .unwrap_or_default();
+        let tags = self.tags.clone().unwrap_or(front.tags);
+        Ok((title, tags, content))
+    }
+
+    /// Let the server render the file and wrap the result into a standalone page.
+    async fn render(&self, remote: &Remote, version: Option<u64>) -> anyhow::Result<String> {
+        let (title, tags, content) = self.read()?;
+        let request = JsonRequest::Preview {
+            kind: self.kind,
+            title: title.clone(),
+            content,
+            tags: tags.clone(),
+        };
+        match remote.send(request).await? {
+            JsonResponse::PreviewResult { content, abstract_content, table_of_contents } => Ok(PreviewTemplate {
+                site: remote.site_url(),
+                title: title.as_str(),
+                tags: tags.as_slice(),
+                content: content.as_str(),
+                abstract_content: abstract_content.as_deref(),
+                table_of_contents: table_of_contents.as_deref(),
+                version,
+            }.render()?),
+            JsonResponse::Error(e) => Err(anyhow!("{}", e)),
+            _ => Err(anyhow!("unexpected server reply"))
+        }
+    }
+
+    pub async fn write(&self, remote: &Remote, output: &Path) -> anyhow::Result<()> {
+        std::fs::write(output, self.render(remote, None).await?)?;
+        println!("preview written to {:?}", output);
+        Ok(())
+    }
+
+    /// Serve the preview on `127.0.0.1:<port>` and render it again whenever the file changes.
+    pub async fn serve(&self, remote: &Remote, port: u16) -> anyhow::Result<()> {
+        let state = LiveState {
+            version: Arc::new(AtomicU64::new(0)),
+            page: Arc::new(RwLock::new(String::new())),
+        };
+        let mut server = tide::with_state(state.clone());
+        server.at("/").get(serve_page);
+        server.at("/version").get(serve_version);
+        let address = format!("127.0.0.1:{}", port);
+        println!("serving the preview of {:?} at http://{}", self.file, address);
+        let mut listener = async_std::task::spawn(server.listen(address));
+        let mut modified = None;
+        loop {
+            let current = std::fs::metadata(self.file.as_path())
+                .and_then(|x| x.modified())
+                .ok();
+            if current != modified {
+                modified = current;
+                let version = state.version.load(Ordering::SeqCst) + 1;
+                match self.render(remote, Some(version)).await {
+                    Ok(page) => {
+                        *state.page.write().unwrap() = page;
+                        state.version.store(version, Ordering::SeqCst);
+                    }
+                    Err(e) => eprintln!("failed to render {:?}: {}", self.file, e),
+                }
+            }
+            if let Ok(result) = async_std::future::timeout(Duration::from_millis(500), &mut listener).await {
+                return result.map_err(Into::into);
+            }
+        }
+    }
+}
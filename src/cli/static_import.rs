@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde_json::Value;
+
+use crate::api::ImportedPost;
+
+/// Static site generators whose content directories can be imported.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum StaticFormat {
+    Hugo,
+    Jekyll,
+    Zola,
+}
+
+impl FromStr for StaticFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "hugo" => Ok(StaticFormat::Hugo),
+            "jekyll" => Ok(StaticFormat::Jekyll),
+            "zola" => Ok(StaticFormat::Zola),
+            _ => Err(anyhow!("unknown format {}, expected hugo, jekyll or zola", s))
+        }
+    }
+}
+
+/// A converted post together with the file it came from and anything that could not be
+/// converted faithfully.
+pub struct Converted {
+    pub path: PathBuf,
+    pub post: ImportedPost,
+    pub warnings: Vec<String>,
+}
+
+fn markdown_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|x| x.to_str()).unwrap_or_default();
+        if path.is_dir() {
+            if !name.starts_with('.') {
+                markdown_files(path.as_path(), files)?;
+            }
+        } else if (name.ends_with(".md") || name.ends_with(".markdown")) && !name.starts_with("_index.") {
+            // `_index.md` is a section page in hugo and zola, not a post
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Split off the front matter; `---` fences hold YAML and `+++` fences hold TOML.
+fn front_matter(document: &str) -> anyhow::Result<(Value, &str)> {
+    if let Some((yaml, content)) = crate::cli::archive::split_document(document) {
+        return Ok((serde_yaml::from_str(yaml)?, content));
+    }
+    let start = match document.find('\n') {
+        Some(x) if document[..x].trim_end() == "+++" => x + 1,
+        _ => return Err(anyhow!("missing front matter"))
+    };
+    let end = document[start..].find("\n+++")
+        .map(|x| start + x)
+        .ok_or_else(|| anyhow!("unterminated front matter"))?;
+    let table: toml::Value = toml::from_str(&document[start..end])?;
+    let content = document[end + 4..].trim_start_matches(|x| x == '\r' || x == '\n');
+    Ok((serde_json::to_value(table)?, content))
+}
+
+fn string_field(value: &Value) -> Option<String> {
+    match value {
+        Value::String(x) => Some(x.clone()),
+        // toml datetimes serialize as a single field map
+        Value::Object(map) if map.len() == 1 => map.values().next().and_then(string_field),
+        _ => None
+    }
+}
+
+fn parse_date(date: &str) -> Option<chrono::NaiveDateTime> {
+    let date = date.trim();
+    chrono::DateTime::parse_from_rfc3339(date)
+        .map(|x| x.naive_utc())
+        .or_else(|_| chrono::DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z").map(|x| x.naive_utc()))
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S"))
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map(|x| x.and_hms(0, 0, 0)))
+        .ok()
+}
+
+/// Jekyll posts are named `YYYY-MM-DD-title.md`.
+fn filename_date(stem: &str) -> Option<chrono::NaiveDateTime> {
+    stem.get(..10).and_then(parse_date)
+}
+
+fn tag_list(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(tags)) => tags.iter().filter_map(string_field).collect(),
+        // jekyll allows a space separated string
+        Some(Value::String(tags)) => tags.split(|x: char| x == ',' || x.is_whitespace())
+            .filter(|x| !x.is_empty())
+            .map(ToString::to_string)
+            .collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Rewrite math delimiters and the common shortcodes into plain markdown with `$`/`$$` math.
+fn convert_content(content: &str, format: StaticFormat, warnings: &mut Vec<String>) -> String {
+    let mut content = content.to_string();
+    if format == StaticFormat::Hugo {
+        // hugo sites escape the backslash so that markdown leaves `\(` and `\[` to the browser
+        content = content
+            .replace("\\\\(", "$").replace("\\\\)", "$")
+            .replace("\\\\[", "$$").replace("\\\\]", "$$");
+    }
+    let rules: &[(&str, &str)] = match format {
+        StaticFormat::Hugo => &[
+            (r"\{\{[<%]\s*highlight\s+(\w+)[^>%]*[>%]\}\}", "```$1"),
+            (r"\{\{[<%]\s*/highlight\s*[>%]\}\}", "```"),
+            (r"(?s)\{\{[<%]\s*(?:katex|math)[^>%]*[>%]\}\}(.*?)\{\{[<%]\s*/(?:katex|math)\s*[>%]\}\}", "$$$$${1}$$$$"),
+            (r#"\{\{[<%]\s*figure\s+src="([^"]*)"(?:[^>%]*caption="([^"]*)")?[^>%]*[>%]\}\}"#, "![$2]($1)"),
+        ],
+        StaticFormat::Jekyll => &[
+            (r"\{%\s*highlight\s+(\w+)[^%]*%\}", "```$1"),
+            (r"\{%\s*endhighlight\s*%\}", "```"),
+            (r"\{%\s*(raw|endraw)\s*%\}", ""),
+            (r"\{\{\s*site\.baseurl\s*\}\}", ""),
+        ],
+        StaticFormat::Zola => &[
+            (r"(?s)\{%\s*katex\([^)]*\)\s*%\}(.*?)\{%\s*end\s*%\}", "$$$$${1}$$$$"),
+        ],
+    };
+    for (pattern, replacement) in rules {
+        let re = regex::Regex::new(pattern).unwrap();
+        content = re.replace_all(content.as_str(), *replacement).to_string();
+    }
+    let leftover = match format {
+        StaticFormat::Hugo => r"\{\{[<%].*?[>%]\}\}",
+        StaticFormat::Jekyll => r"\{%.*?%\}",
+        StaticFormat::Zola => r"\{\{\s*\w+\(.*?\)\s*\}\}|\{%.*?%\}",
+    };
+    for shortcode in regex::Regex::new(leftover).unwrap().find_iter(content.as_str()) {
+        warnings.push(format!("unconverted shortcode {}", shortcode.as_str()));
+    }
+    content
+}
+
+fn convert_file(path: &Path, format: StaticFormat) -> anyhow::Result<Converted> {
+    let document = std::fs::read_to_string(path)?;
+    let (meta, content) = front_matter(document.as_str())?;
+    let mut warnings = Vec::new();
+    let stem = path.file_stem().and_then(|x| x.to_str()).unwrap_or_default();
+    let title = meta.get("title")
+        .and_then(string_field)
+        .ok_or_else(|| anyhow!("no title"))?;
+    let mut public_date = meta.get("date")
+        .and_then(string_field)
+        .and_then(|x| parse_date(x.as_str()));
+    if public_date.is_none() && format == StaticFormat::Jekyll {
+        public_date = filename_date(stem);
+    }
+    if public_date.is_none() {
+        warnings.push("no date, it will be published now".to_string());
+    }
+    let tags = match format {
+        StaticFormat::Zola => tag_list(meta.get("taxonomies").and_then(|x| x.get("tags"))),
+        _ => tag_list(meta.get("tags")),
+    };
+    let draft = match format {
+        StaticFormat::Jekyll => meta.get("published").and_then(Value::as_bool) == Some(false),
+        _ => meta.get("draft").and_then(Value::as_bool).unwrap_or(false),
+    };
+    Ok(Converted {
+        path: path.to_path_buf(),
+        post: ImportedPost {
+            title,
+            content: convert_content(content, format, &mut warnings),
+            tags,
+            public_date,
+            draft,
+            slug: meta.get("slug").and_then(string_field),
+        },
+        warnings,
+    })
+}
+
+/// Convert every markdown post under `dir`; files that cannot be read are returned as errors
+/// so that one bad file does not stop the import.
+pub fn convert_dir(dir: &Path, format: StaticFormat) -> anyhow::Result<(Vec<Converted>, Vec<(PathBuf, anyhow::Error)>)> {
+    let mut files = Vec::new();
+    markdown_files(dir, &mut files)?;
+    files.sort();
+    let mut converted = Vec::new();
+    let mut failed = Vec::new();
+    for file in files {
+        match convert_file(file.as_path(), format) {
+            Ok(x) => converted.push(x),
+            Err(e) => failed.push((file, e)),
+        }
+    }
+    Ok((converted, failed))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use super::{convert_content, filename_date, front_matter, parse_date, string_field, StaticFormat};
+
+    fn convert(content: &str, format: StaticFormat) -> String {
+        convert_content(content, format, &mut Vec::new())
+    }
+
+    #[test]
+    fn converts_shortcodes() {
+        assert_eq!(convert("{{< highlight rust \"linenos=table\" >}}\nfn main() {}\n{{< /highlight >}}", StaticFormat::Hugo),
+                   "```rust\nfn main() {}\n```");
+        assert_eq!(convert("{{< katex >}}\\sum_i x_i{{< /katex >}}", StaticFormat::Hugo), "$$\\sum_i x_i$$");
+        assert_eq!(convert("\\\\(a\\\\) and \\\\[b\\\\]", StaticFormat::Hugo), "$a$ and $$b$$");
+        assert_eq!(convert("{% highlight ruby %}\nputs 1\n{% endhighlight %}", StaticFormat::Jekyll),
+                   "```ruby\nputs 1\n```");
+        assert_eq!(convert("{% katex(block=true) %}x^2{% end %}", StaticFormat::Zola), "$$x^2$$");
+    }
+
+    #[test]
+    fn warns_about_unconverted_shortcodes() {
+        let mut warnings = Vec::new();
+        convert_content("{{< youtube abc >}}", StaticFormat::Hugo, &mut warnings);
+        assert_eq!(warnings, vec!["unconverted shortcode {{< youtube abc >}}"]);
+    }
+
+    #[test]
+    fn parses_dates() {
+        let expected = NaiveDate::from_ymd(2020, 6, 1).and_hms(10, 30, 0);
+        assert_eq!(parse_date("2020-06-01T12:30:00+02:00"), Some(expected));
+        assert_eq!(parse_date("2020-06-01 10:30:00 +0000"), Some(expected));
+        assert_eq!(parse_date("2020-06-01 10:30:00"), Some(expected));
+        assert_eq!(filename_date("2020-06-01-hello-world"), Some(NaiveDate::from_ymd(2020, 6, 1).and_hms(0, 0, 0)));
+        assert_eq!(filename_date("hello-world"), None);
+    }
+
+    #[test]
+    fn reads_toml_datetimes() {
+        let (meta, content) = front_matter("+++\ntitle = \"a\"\ndate = 2020-06-01T10:30:00Z\n+++\nbody\n").unwrap();
+        let date = meta.get("date").and_then(string_field).and_then(|x| parse_date(x.as_str()));
+        assert_eq!(date, Some(NaiveDate::from_ymd(2020, 6, 1).and_hms(10, 30, 0)));
+        assert_eq!(content, "body\n");
+    }
+}
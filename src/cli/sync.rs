@@ -0,0 +1,299 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::api::{ContentDigest, JsonRequest, JsonResponse, ModelType, page_digest, post_digest};
+use crate::cli::archive::{format_document, split_document};
+use crate::cli::client::Remote;
+use crate::model::PostStatus;
+use crate::utils::{confirm, normalize_tags, slugify};
+
+/// Front matter of a synced file. The fields written by `export` are understood as well, so an
+/// unpacked archive can be synced back.
+#[derive(serde::Deserialize)]
+struct SyncFrontMatter {
+    #[serde(default)]
+    id: Option<i32>,
+    title: String,
+    #[serde(default)]
+    slug: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    status: Option<PostStatus>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    important: bool,
+    #[serde(default)]
+    toc: Option<bool>,
+}
+
+#[derive(serde::Serialize)]
+struct PostSummary<'a> {
+    title: &'a str,
+    slug: &'a str,
+    tags: &'a [String],
+    toc: bool,
+    draft: bool,
+}
+
+#[derive(serde::Serialize)]
+struct PageSummary<'a> {
+    title: &'a str,
+    slug: &'a str,
+    description: &'a str,
+    important: bool,
+}
+
+struct LocalFile {
+    path: PathBuf,
+    model_type: ModelType,
+    front: SyncFrontMatter,
+    slug: String,
+    tags: Vec<String>,
+    content: String,
+    draft: bool,
+    digest: String,
+}
+
+enum Change {
+    Create(LocalFile),
+    Update(LocalFile, ContentDigest),
+    Delete(ContentDigest),
+}
+
+fn markdown_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            markdown_files(path.as_path(), files)?;
+        } else if path.extension().map(|x| x == "md").unwrap_or(false) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_file(path: PathBuf, model_type: ModelType) -> anyhow::Result<LocalFile> {
+    let document = std::fs::read_to_string(path.as_path())?;
+    let (front, content) = split_document(document.as_str())
+        .ok_or_else(|| anyhow!("{:?}: missing front matter", path))?;
+    let front: SyncFrontMatter = serde_yaml::from_str(front)
+        .map_err(|e| anyhow!("{:?}: {}", path, e))?;
+    let slug = slugify(front.slug.as_ref().unwrap_or(&front.title));
+    if slug.is_empty() {
+        return Err(anyhow!("{:?}: empty slug", path));
+    }
+    let tags = normalize_tags(front.tags.iter().cloned());
+    let draft = front.draft || front.status == Some(PostStatus::Draft);
+    let digest = match model_type {
+        ModelType::Page => page_digest(front.title.as_str(), slug.as_str(), front.description.as_str(),
+                                       front.important, content)?,
+        _ => post_digest(front.title.as_str(), slug.as_str(), tags.as_slice(), front.toc.unwrap_or(true),
+                         draft, content)?,
+    };
+    Ok(LocalFile {
+        path,
+        model_type,
+        front,
+        slug,
+        tags,
+        content: content.to_string(),
+        draft,
+        digest,
+    })
+}
+
+/// Posts live under `posts/` and pages under `pages/`; a kind whose directory is missing is not
+/// synced at all, so that pointing at a posts-only directory does not delete every page.
+fn read_dir(dir: &Path) -> anyhow::Result<(Vec<LocalFile>, Vec<ModelType>)> {
+    let mut local = Vec::new();
+    let mut synced = Vec::new();
+    for (name, model_type) in [("posts", ModelType::Post), ("pages", ModelType::Page)].iter() {
+        let sub_dir = dir.join(name);
+        if !sub_dir.is_dir() {
+            continue;
+        }
+        synced.push(*model_type);
+        let mut files = Vec::new();
+        markdown_files(sub_dir.as_path(), &mut files)?;
+        files.sort();
+        for file in files {
+            local.push(read_file(file, *model_type)?);
+        }
+    }
+    if synced.is_empty() {
+        return Err(anyhow!("{:?} has neither a posts nor a pages directory", dir));
+    }
+    Ok((local, synced))
+}
+
+fn plan(local: Vec<LocalFile>, mut remote: Vec<ContentDigest>, synced: &[ModelType]) -> anyhow::Result<Vec<Change>> {
+    remote.retain(|x| synced.contains(&x.model_type));
+    let mut changes = Vec::new();
+    for (index, file) in local.iter().enumerate() {
+        if let Some(other) = local[..index].iter()
+            .find(|x| x.model_type == file.model_type && x.slug == file.slug) {
+            return Err(anyhow!("{:?} and {:?} have the same slug {}", other.path, file.path, file.slug));
+        }
+    }
+    for file in local {
+        // an id from the front matter wins, so that renaming a slug updates the old model
+        let found = remote.iter()
+            .position(|x| x.model_type == file.model_type && Some(x.id) == file.front.id)
+            .or_else(|| remote.iter()
+                .position(|x| x.model_type == file.model_type && x.slug == file.slug));
+        match found {
+            Some(index) => {
+                let current = remote.remove(index);
+                if current.digest != file.digest {
+                    changes.push(Change::Update(file, current));
+                }
+            }
+            None => changes.push(Change::Create(file)),
+        }
+    }
+    changes.extend(remote.into_iter().map(Change::Delete));
+    Ok(changes)
+}
+
+fn local_document(file: &LocalFile) -> anyhow::Result<String> {
+    match file.model_type {
+        ModelType::Page => format_document(&PageSummary {
+            title: file.front.title.as_str(),
+            slug: file.slug.as_str(),
+            description: file.front.description.as_str(),
+            important: file.front.important,
+        }, file.content.as_str()),
+        _ => format_document(&PostSummary {
+            title: file.front.title.as_str(),
+            slug: file.slug.as_str(),
+            tags: file.tags.as_slice(),
+            toc: file.front.toc.unwrap_or(true),
+            draft: file.draft,
+        }, file.content.as_str()),
+    }
+}
+
+async fn remote_document(remote: &Remote, current: &ContentDigest) -> anyhow::Result<String> {
+    match remote.send(JsonRequest::CheckOperation { id: current.id, check_type: current.model_type }).await? {
+        JsonResponse::PostInfo(post) => format_document(&PostSummary {
+            title: post.title.as_str(),
+            slug: post.slug.as_str(),
+            tags: post.tags.as_slice(),
+            toc: post.toc,
+            draft: post.status == PostStatus::Draft,
+        }, post.content.as_str()),
+        JsonResponse::PageInfo(page) => format_document(&PageSummary {
+            title: page.title.as_str(),
+            slug: page.slug.as_str(),
+            description: page.description.as_str(),
+            important: page.important,
+        }, page.content.as_str()),
+        JsonResponse::Error(e) => Err(anyhow!("{}", e)),
+        _ => Err(anyhow!("unexpected server reply"))
+    }
+}
+
+fn into_request(change: Change) -> JsonRequest {
+    match change {
+        Change::Create(file) => match file.model_type {
+            ModelType::Page => JsonRequest::PageCreate {
+                title: file.front.title,
+                content: file.content,
+                description: file.front.description,
+                important: file.front.important,
+                slug: Some(file.slug),
+            },
+            _ => JsonRequest::PostCreate {
+                title: file.front.title,
+                content: file.content,
+                tag: file.tags,
+                draft: file.draft,
+                publish_at: None,
+                slug: Some(file.slug),
+                toc: file.front.toc,
+            },
+        },
+        Change::Update(file, current) => match file.model_type {
+            ModelType::Page => JsonRequest::PageUpdate {
+                id: current.id,
+                title: Some(file.front.title),
+                content: Some(file.content),
+                important: Some(file.front.important),
+                description: Some(file.front.description),
+                slug: Some(file.slug),
+                expected_digest: Some(current.digest),
+            },
+            _ => JsonRequest::PostUpdate {
+                id: current.id,
+                title: Some(file.front.title),
+                tags: Some(file.tags),
+                content: Some(file.content),
+                slug: Some(file.slug),
+                if_unmodified_since: current.update_date,
+                toc: Some(file.front.toc.unwrap_or(true)),
+                draft: Some(file.draft),
+            },
+        },
+        Change::Delete(current) => JsonRequest::DeleteOperation {
+            id: current.id,
+            delete_type: current.model_type,
+        },
+    }
+}
+
+/// Make the posts and pages on the server match the markdown files under `dir`.
+pub async fn sync(remote: &Remote, dir: &Path, dry_run: bool) -> anyhow::Result<()> {
+    let (local, synced) = read_dir(dir)?;
+    let digests = match remote.send(JsonRequest::ContentDigests).await? {
+        JsonResponse::ContentDigestList(x) => x,
+        JsonResponse::Error(e) => return Err(anyhow!("{}", e)),
+        _ => return Err(anyhow!("unexpected server reply"))
+    };
+    let changes = plan(local, digests, synced.as_slice())?;
+    if changes.is_empty() {
+        println!("everything is up to date");
+        return Ok(());
+    }
+    let mut deletions = 0;
+    for change in changes.iter() {
+        match change {
+            Change::Create(file) => println!("create {} {} from {:?}",
+                                             file.model_type.as_str(), file.slug, file.path),
+            Change::Update(file, current) => {
+                println!("update {} {} from {:?}", current.model_type.as_str(), current.id, file.path);
+                if dry_run {
+                    let old = remote_document(remote, current).await?;
+                    let new = local_document(file)?;
+                    let diff = similar::TextDiff::from_lines(old.as_str(), new.as_str());
+                    print!("{}", diff.unified_diff()
+                        .context_radius(3)
+                        .header(format!("{} {}", current.model_type.as_str(), current.id).as_str(),
+                                format!("{:?}", file.path).as_str()));
+                }
+            }
+            Change::Delete(current) => {
+                deletions += 1;
+                println!("delete {} {} {}", current.model_type.as_str(), current.id, current.slug);
+            }
+        }
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if deletions > 0 {
+        confirm(format!("delete {} posts or pages that are not in {:?}", deletions, dir))?;
+    }
+    let batch = JsonRequest::Batch(changes.into_iter().map(into_request).collect());
+    match remote.send(batch).await? {
+        JsonResponse::BatchResult { committed: true, .. } => {
+            println!("synced {:?}", dir);
+            Ok(())
+        }
+        response => Err(anyhow!("nothing was changed: {}", response.error().unwrap_or("unexpected server reply")))
+    }
+}
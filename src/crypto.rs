@@ -2,14 +2,18 @@ use std::ops::Add;
 use std::time::{Duration, UNIX_EPOCH};
 
 use anyhow::*;
+use async_diesel::*;
+use diesel::prelude::*;
 use radix64::STD as base64;
 use serde::Serialize;
 use tide::{Status, StatusCode};
 use xactor::Context;
 
-use crate::KeyPair;
+use crate::{ConnPool, KeyPair};
 
 const TIME_OUT: u64 = 30;
+/// How long a comment challenge stays valid after the post page was rendered.
+pub const CHALLENGE_TIME_OUT: u64 = 60 * 60;
 
 #[derive(Eq, Ord, PartialOrd, PartialEq, Debug)]
 struct Stamp {
@@ -25,15 +29,30 @@ struct CleanUp;
 #[xactor::message(result = "bool")]
 struct PutStamp(Stamp);
 
-#[derive(Default)]
 pub struct StampKeeper {
-    stamps: std::collections::BTreeSet<Stamp>
+    stamps: std::collections::BTreeSet<Stamp>,
+    time_out: u64,
+}
+
+impl Default for StampKeeper {
+    fn default() -> Self {
+        StampKeeper::with_time_out(TIME_OUT)
+    }
+}
+
+impl StampKeeper {
+    pub fn with_time_out(time_out: u64) -> Self {
+        StampKeeper {
+            stamps: Default::default(),
+            time_out,
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl xactor::Actor for StampKeeper {
     async fn started(&mut self, ctx: &mut Context<Self>) -> xactor::Result<()> {
-        Ok(ctx.send_interval(CleanUp, Duration::from_secs(TIME_OUT / 2)))
+        Ok(ctx.send_interval(CleanUp, Duration::from_secs(self.time_out / 2)))
     }
 }
 
@@ -42,7 +61,7 @@ impl xactor::Handler<CleanUp> for StampKeeper {
     async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: CleanUp) {
         while !self.stamps.is_empty()
             && UNIX_EPOCH.add(Duration::from_secs(self.stamps.first().unwrap().time_stamp))
-            .elapsed().unwrap().as_secs() > TIME_OUT {
+            .elapsed().unwrap().as_secs() > self.time_out {
             self.stamps.pop_first();
         }
     }
@@ -60,9 +79,418 @@ impl xactor::Handler<PutStamp> for StampKeeper {
     }
 }
 
+/// Remembers which `(time_stamp, nonce)` pairs the admin API has already accepted.
+#[async_trait::async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Record a stamp; returns false if it has been seen within the time out.
+    async fn put(&self, time_stamp: u64, nonce: &str) -> anyhow::Result<bool>;
+}
+
+#[async_trait::async_trait]
+impl NonceStore for xactor::Addr<StampKeeper> {
+    async fn put(&self, time_stamp: u64, nonce: &str) -> anyhow::Result<bool> {
+        self.call(PutStamp(Stamp { time_stamp, nonce: nonce.to_string() })).await
+    }
+}
+
+/// A nonce store shared through Postgres, so that stamps survive restarts and are seen
+/// by every server instance using the same database.
+pub struct PgNonceStore {
+    pool: ConnPool,
+}
+
+impl PgNonceStore {
+    /// Create the store and spawn a task removing stamps older than the time out.
+    pub fn start(pool: ConnPool) -> Self {
+        let cleaner = pool.clone();
+        async_std::task::spawn(async move {
+            use crate::schema::api_nonces::dsl as n;
+            loop {
+                async_std::task::sleep(Duration::from_secs(TIME_OUT / 2)).await;
+                let expired = std::time::SystemTime::now().duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_secs().saturating_sub(TIME_OUT) as i64)
+                    .unwrap_or(0);
+                if let Err(e) = diesel::delete(n::api_nonces.filter(n::time_stamp.lt(expired)))
+                    .execute_async(&cleaner)
+                    .await {
+                    log::error!("failed to clean up nonces: {}", e);
+                }
+            }
+        });
+        PgNonceStore { pool }
+    }
+}
+
+#[async_trait::async_trait]
+impl NonceStore for PgNonceStore {
+    async fn put(&self, time_stamp: u64, nonce: &str) -> anyhow::Result<bool> {
+        use crate::schema::api_nonces::dsl as n;
+        diesel::insert_into(n::api_nonces)
+            .values((n::time_stamp.eq(time_stamp as i64), n::nonce.eq(nonce.to_string())))
+            .on_conflict_do_nothing()
+            .execute_async(&self.pool)
+            .await
+            .map(|x| x == 1)
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+/// Where the admin API keeps seen nonces.
+#[derive(Copy, Clone, Debug)]
+pub enum NonceStoreKind {
+    Memory,
+    Postgres,
+}
+
+impl std::str::FromStr for NonceStoreKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "memory" => Ok(NonceStoreKind::Memory),
+            "postgres" => Ok(NonceStoreKind::Postgres),
+            _ => Err(anyhow!("unknown nonce store {}, expected memory or postgres", s))
+        }
+    }
+}
+
+/// A hashcash style challenge handed out with every rendered post page. The token is
+/// `post_id:time_stamp:difficulty:nonce:mac`, where the mac binds the other fields to this
+/// server; a reader proves work by finding a counter such that `SHA-256(token:counter)`
+/// starts with `difficulty` zero bits.
+#[derive(Debug)]
+pub struct Challenge {
+    post_id: i32,
+    time_stamp: u64,
+    difficulty: u32,
+    nonce: String,
+    mac: String,
+}
+
+impl Challenge {
+    pub fn issue(secret: &[u8], post_id: i32, difficulty: u32) -> anyhow::Result<Self> {
+        let mut nonce = [0; 16];
+        botan::RandomNumberGenerator::new_system()
+            .map_err(|e| anyhow!("{:?}", e))?
+            .fill(&mut nonce)
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let time_stamp = std::time::SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut challenge = Challenge {
+            post_id,
+            time_stamp,
+            difficulty,
+            nonce: hex::encode(nonce),
+            mac: String::new(),
+        };
+        challenge.mac = challenge.compute_mac(secret)?;
+        Ok(challenge)
+    }
+
+    pub fn parse(token: &str) -> anyhow::Result<Self> {
+        let fields: Vec<&str> = token.split(':').collect();
+        if fields.len() != 5 {
+            return Err(anyhow!("malformed challenge"));
+        }
+        Ok(Challenge {
+            post_id: fields[0].parse()?,
+            time_stamp: fields[1].parse()?,
+            difficulty: fields[2].parse()?,
+            nonce: fields[3].to_string(),
+            mac: fields[4].to_string(),
+        })
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn token(&self) -> String {
+        format!("{}:{}", self.payload(), self.mac)
+    }
+
+    fn payload(&self) -> String {
+        format!("{}:{}:{}:{}", self.post_id, self.time_stamp, self.difficulty, self.nonce)
+    }
+
+    fn compute_mac(&self, secret: &[u8]) -> anyhow::Result<String> {
+        let mac = botan::MsgAuthCode::new("HMAC(SHA-256)")
+            .map_err(|e| anyhow!("{:?}", e))?;
+        mac.set_key(secret)
+            .and_then(|_| mac.update(self.payload().as_bytes()))
+            .map_err(|e| anyhow!("{:?}", e))?;
+        mac.finish()
+            .map(hex::encode)
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    /// Check that the challenge was issued by this server for `post_id`, has not expired or
+    /// been used before, asks for at least `difficulty` bits, and that `counter` solves it.
+    pub async fn verify(&self,
+                        secret: &[u8],
+                        post_id: i32,
+                        difficulty: u32,
+                        counter: &str,
+                        stamp_keeper: &mut xactor::Addr<StampKeeper>) -> anyhow::Result<()> {
+        if !botan::const_time_compare(self.compute_mac(secret)?.as_bytes(), self.mac.as_bytes()) {
+            return Err(anyhow!("challenge was not issued by this server"));
+        }
+        if self.post_id != post_id || self.difficulty < difficulty {
+            return Err(anyhow!("challenge does not match the post"));
+        }
+        if UNIX_EPOCH.add(Duration::from_secs(self.time_stamp)).elapsed()
+            .map(|x| x.as_secs() > CHALLENGE_TIME_OUT)
+            .unwrap_or(true) {
+            return Err(anyhow!("challenge expired"));
+        }
+        let hash = botan::HashFunction::new("SHA-256")
+            .map_err(|e| anyhow!("{:?}", e))?;
+        hash.update(format!("{}:{}", self.token(), counter).as_bytes())
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let digest = hash.finish()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        if leading_zero_bits(digest.as_slice()) < self.difficulty {
+            return Err(anyhow!("insufficient proof of work"));
+        }
+        match stamp_keeper.call(PutStamp(Stamp { time_stamp: self.time_stamp, nonce: self.nonce.clone() })).await {
+            Ok(true) => Ok(()),
+            _ => Err(anyhow!("challenge already used"))
+        }
+    }
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    for i in bytes {
+        count += i.leading_zeros();
+        if *i != 0 {
+            break;
+        }
+    }
+    count
+}
+
+/// Key algorithm of a deployment. RSA keys both transport the AEAD key (OAEP) and sign
+/// (PKCS#1 v1.5); Ed25519 keys sign and are paired with an X25519 key for key agreement.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Rsa,
+    Ed25519,
+}
+
+impl Default for Algorithm {
+    /// Packets from before the algorithm field existed are RSA.
+    fn default() -> Self {
+        Algorithm::Rsa
+    }
+}
+
+impl std::str::FromStr for Algorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rsa" => Ok(Algorithm::Rsa),
+            "ed25519" => Ok(Algorithm::Ed25519),
+            _ => Err(anyhow!("unknown algorithm {}, expected rsa or ed25519", s))
+        }
+    }
+}
+
+impl Algorithm {
+    fn signature_padding(self) -> &'static str {
+        match self {
+            Algorithm::Rsa => "PKCS1v15(SHA-256)",
+            Algorithm::Ed25519 => "Pure",
+        }
+    }
+}
+
+/// Split a PEM file into its `-----BEGIN ...-----`/`-----END ...-----` blocks.
+fn pem_blocks(pem: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<String> = None;
+    for line in pem.lines() {
+        if line.starts_with("-----BEGIN") {
+            current = Some(String::new());
+        }
+        if let Some(block) = current.as_mut() {
+            block.push_str(line);
+            block.push('\n');
+        }
+        if line.starts_with("-----END") {
+            blocks.extend(current.take());
+        }
+    }
+    blocks
+}
+
+/// The raw 32 byte X25519 public value, which is the tail of its DER encoding.
+fn x25519_public_value(key: &botan::Pubkey) -> anyhow::Result<Vec<u8>> {
+    let der = key.der_encode().map_err(|e| anyhow!("{:?}", e))?;
+    if der.len() < 32 {
+        return Err(anyhow!("Invalid X25519 Key"));
+    }
+    Ok(der[der.len() - 32..].to_vec())
+}
+
+pub struct PrivateKey {
+    identity: botan::Privkey,
+    exchange: Option<botan::Privkey>,
+}
+
+impl PrivateKey {
+    pub fn generate(algorithm: Algorithm, rsa_length: usize) -> anyhow::Result<Self> {
+        let random = botan::RandomNumberGenerator::new_system()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        match algorithm {
+            Algorithm::Rsa => Ok(PrivateKey {
+                identity: botan::Privkey::create("RSA", rsa_length.to_string().as_str(), &random)
+                    .map_err(|e| anyhow!("{:?}", e))?,
+                exchange: None,
+            }),
+            Algorithm::Ed25519 => Ok(PrivateKey {
+                identity: botan::Privkey::create("Ed25519", "", &random)
+                    .map_err(|e| anyhow!("{:?}", e))?,
+                exchange: Some(botan::Privkey::create("Curve25519", "", &random)
+                    .map_err(|e| anyhow!("{:?}", e))?),
+            })
+        }
+    }
+
+    /// Load a private key file; Ed25519 files hold the Ed25519 key followed by the X25519 key.
+    pub fn load(pem: &str, password: Option<&str>) -> anyhow::Result<Self> {
+        let mut identity = None;
+        let mut exchange = None;
+        for block in pem_blocks(pem) {
+            let key = match password {
+                Some(password) => botan::Privkey::load_encrypted_pem(block.as_str(), password),
+                None => botan::Privkey::load_pem(block.as_str()),
+            }.map_err(|e| anyhow!("{:?}", e))?;
+            match key.algo_name().map_err(|e| anyhow!("{:?}", e))?.as_str() {
+                "Curve25519" => exchange = Some(key),
+                _ => identity = Some(key),
+            }
+        }
+        let key = PrivateKey {
+            identity: identity.ok_or_else(|| anyhow!("no private key found"))?,
+            exchange,
+        };
+        key.algorithm().map(|_| key)
+    }
+
+    pub fn algorithm(&self) -> anyhow::Result<Algorithm> {
+        let name = self.identity.algo_name().map_err(|e| anyhow!("{:?}", e))?;
+        match (name.as_str(), self.exchange.is_some()) {
+            ("RSA", false) => Ok(Algorithm::Rsa),
+            ("Ed25519", true) => Ok(Algorithm::Ed25519),
+            ("Ed25519", false) => Err(anyhow!("Ed25519 key file is missing its X25519 key")),
+            _ => Err(anyhow!("unsupported private key {}", name))
+        }
+    }
+
+    pub fn pem_encode(&self, password: Option<&str>) -> anyhow::Result<String> {
+        let random = botan::RandomNumberGenerator::new_system()
+            .map_err(|e| anyhow!("{:?}", e))?;
+        let mut pem = String::new();
+        for key in std::iter::once(&self.identity).chain(self.exchange.iter()) {
+            pem.push_str(match password {
+                Some(password) => key.pem_encode_encrypted(password, &random),
+                None => key.pem_encode(),
+            }.map_err(|e| anyhow!("{:?}", e))?.as_str());
+        }
+        Ok(pem)
+    }
+
+    pub fn public_key(&self) -> anyhow::Result<PublicKey> {
+        Ok(PublicKey {
+            identity: self.identity.pubkey().map_err(|e| anyhow!("{:?}", e))?,
+            exchange: match self.exchange.as_ref() {
+                Some(key) => Some(key.pubkey().map_err(|e| anyhow!("{:?}", e))?),
+                None => None
+            },
+        })
+    }
+}
+
+pub struct PublicKey {
+    identity: botan::Pubkey,
+    exchange: Option<botan::Pubkey>,
+}
+
+unsafe impl Send for PrivateKey {}
+
+unsafe impl Sync for PrivateKey {}
+
+unsafe impl Send for PublicKey {}
+
+unsafe impl Sync for PublicKey {}
+
+impl PublicKey {
+    pub fn load(pem: &str) -> anyhow::Result<Self> {
+        let mut identity = None;
+        let mut exchange = None;
+        for block in pem_blocks(pem) {
+            let key = botan::Pubkey::load_pem(block.as_str())
+                .map_err(|e| anyhow!("{:?}", e))?;
+            match key.algo_name().map_err(|e| anyhow!("{:?}", e))?.as_str() {
+                "Curve25519" => exchange = Some(key),
+                _ => identity = Some(key),
+            }
+        }
+        let key = PublicKey {
+            identity: identity.ok_or_else(|| anyhow!("no public key found"))?,
+            exchange,
+        };
+        key.algorithm().map(|_| key)
+    }
+
+    pub fn algorithm(&self) -> anyhow::Result<Algorithm> {
+        let name = self.identity.algo_name().map_err(|e| anyhow!("{:?}", e))?;
+        match (name.as_str(), self.exchange.is_some()) {
+            ("RSA", false) => Ok(Algorithm::Rsa),
+            ("Ed25519", true) => Ok(Algorithm::Ed25519),
+            ("Ed25519", false) => Err(anyhow!("Ed25519 key file is missing its X25519 key")),
+            _ => Err(anyhow!("unsupported public key {}", name))
+        }
+    }
+
+    pub fn pem_encode(&self) -> anyhow::Result<String> {
+        let mut pem = String::new();
+        for key in std::iter::once(&self.identity).chain(self.exchange.iter()) {
+            pem.push_str(key.pem_encode().map_err(|e| anyhow!("{:?}", e))?.as_str());
+        }
+        Ok(pem)
+    }
+
+    pub fn fingerprint(&self) -> anyhow::Result<String> {
+        self.identity.fingerprint("SHA-256")
+            .map_err(|e| anyhow!("{:?}", e))
+    }
+}
+
+/// Both ends of a packet have to use the same algorithm.
+fn common_algorithm(private: &PrivateKey, peer: &PublicKey) -> anyhow::Result<Algorithm> {
+    let algorithm = private.algorithm()?;
+    if algorithm != peer.algorithm()? {
+        return Err(anyhow!("private and public keys use different algorithms"));
+    }
+    Ok(algorithm)
+}
+
+impl KeyPair {
+    pub fn algorithm(&self) -> anyhow::Result<Algorithm> {
+        common_algorithm(&self.server_private, &self.owner_public)
+    }
+}
 
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct Packet {
+    #[serde(default)]
+    algorithm: Algorithm,
+    /// Fingerprint of the signing key; packets without it are signed by the startup owner key.
+    #[serde(default)]
+    key_id: Option<String>,
     symmetric_key: String,
     nonce: String,
     message_block: String,
@@ -71,10 +499,16 @@ pub struct Packet {
 }
 
 impl Packet {
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    /// Decrypt with `private` and check the signature made by `peer`.
     pub async fn to_json_request<T: serde::de::DeserializeOwned>(
         &self,
-        key_pair: &KeyPair,
-        mut stamp_keeper: Option<&mut xactor::Addr<StampKeeper>>,
+        private: &PrivateKey,
+        peer: &PublicKey,
+        nonce_store: Option<&dyn NonceStore>,
     ) -> anyhow::Result<T> {
         let time_stamp: u64 = self.time_stamp.parse()?;
         if UNIX_EPOCH.add(Duration::from_secs(time_stamp)).elapsed().status(StatusCode::RequestTimeout)
@@ -82,54 +516,92 @@ impl Packet {
             .map(|x| x.as_secs() > TIME_OUT)? {
             return Err(anyhow!("Time Limit Exceeded"));
         }
-        if stamp_keeper.is_some() {
-            let addr = stamp_keeper.as_mut().unwrap();
-            match addr.call(PutStamp(Stamp { time_stamp, nonce: self.nonce.clone() })).await {
+        if self.algorithm != common_algorithm(private, peer)? {
+            return Err(anyhow!("Algorithm Mismatch"));
+        }
+        let symmetric_key = base64.decode(self.symmetric_key.as_bytes())?;
+        let signature = base64.decode(self.signature.as_bytes())?;
+        let message = base64.decode(self.message_block.as_bytes())?;
+        let nonce = base64.decode(self.nonce.as_bytes())?;
+        let aead_key = match (self.algorithm, private.exchange.as_ref()) {
+            (Algorithm::Ed25519, Some(exchange)) => {
+                botan::KeyAgreement::new(exchange, "KDF2(SHA-256)")
+                    .and_then(|x| x.agree(32, symmetric_key.as_slice(), nonce.as_slice()))
+                    .map_err(|_| anyhow!("Key Agreement Error"))?
+            }
+            _ => {
+                botan::Decryptor::new(&private.identity, "OAEP(SHA-512)")
+                    .map_err(|_| anyhow!("Decryptor Initialization Failed"))?
+                    .decrypt(symmetric_key.as_slice())
+                    .map_err(|_| anyhow!("Invalid AEAD Key"))?
+            }
+        };
+        let verified = {
+            let verifier = botan::Verifier::new(&peer.identity, self.algorithm.signature_padding())
+                .map_err(|_| anyhow!("Verifier Initialization Failed"))?;
+            verifier.update(self.time_stamp.as_bytes())
+                .and_then(|_| verifier.update(aead_key.as_ref()))
+                .and_then(|_| verifier.update(message.as_ref()))
+                .and_then(|_| verifier.update(nonce.as_ref()))
+                .map_err(|_| anyhow!("Verifier Update Error"))?;
+            verifier.finish(signature.as_slice())
+        };
+        if !matches!(verified, Ok(true)) {
+            return Err(anyhow!("Signature Verification Error"));
+        }
+        // only requests the peer has signed may take up room in the nonce store
+        if let Some(store) = nonce_store {
+            match store.put(time_stamp, self.nonce.as_str()).await {
                 Ok(false) | Err(_) => {
                     return Err(anyhow!("Stamp Validation Failed"));
                 }
                 _ => ()
             }
         }
-        let verifier = botan::Verifier::new(&key_pair.owner_public, "PKCS1v15(SHA-256)")
-            .map_err(|_| anyhow!("Verifier Initialization Failed"))?;
-        let decrypter = botan::Decryptor::new(&key_pair.server_private, "OAEP(SHA-512)")
-            .map_err(|_| anyhow!("Decryptor Initialization Failed"))?;
-        let symmetric_key = base64.decode(self.symmetric_key.as_bytes())?;
-        let signature = base64.decode(self.signature.as_bytes())?;
-        let message = base64.decode(self.message_block.as_bytes())?;
-        let nonce = base64.decode(self.nonce.as_bytes())?;
-        let aead_key = decrypter.decrypt(symmetric_key.as_slice())
+        let aead = botan::Cipher::new("AES-256/GCM", botan::CipherDirection::Decrypt)
+            .map_err(|_| anyhow!("AEAD Initialization Error"))?;
+        aead.set_key(aead_key.as_slice())
             .map_err(|_| anyhow!("Invalid AEAD Key"))?;
-        verifier.update(self.time_stamp.as_bytes())
-            .and_then(|_| verifier.update(aead_key.as_ref()))
-            .and_then(|_| verifier.update(message.as_ref()))
-            .and_then(|_| verifier.update(nonce.as_ref()))
-            .map_err(|_| anyhow!("Verifier Update Error"))?;
-        if let Ok(true) = verifier.finish(signature.as_slice()) {
-            let aead = botan::Cipher::new("AES-256/GCM", botan::CipherDirection::Decrypt)
-                .map_err(|_| anyhow!("AEAD Initialization Error"))?;
-            aead.set_key(aead_key.as_slice())
-                .map_err(|_| anyhow!("Invalid AEAD Key"))?;
-            aead.process(nonce.as_slice(), message.as_slice())
-                .map_err(|_| anyhow!("AEAD Process Error"))
-                .and_then(|mut x| simd_json::from_slice(x.as_mut_slice())
-                    .map_err(Into::into))
-        } else {
-            Err(anyhow!("Signature Verification Error"))
-        }
+        aead.process(nonce.as_slice(), message.as_slice())
+            .map_err(|_| anyhow!("AEAD Process Error"))
+            .and_then(|mut x| simd_json::from_slice(x.as_mut_slice())
+                .map_err(Into::into))
     }
 
+    /// Encrypt for `peer` and sign with `private`.
     #[allow(unused)]
-    pub async fn from_json_request<T: Serialize>(res: T, key_pair: &KeyPair) -> anyhow::Result<Self> {
-        let mut aead_key = [0; 32];
+    pub async fn from_json_request<T: Serialize>(res: T, private: &PrivateKey, peer: &PublicKey) -> anyhow::Result<Self> {
         let mut nonce = [0; 12];
-        let privkey = &key_pair.server_private;
-        let pubkey = &key_pair.owner_public;
+        let algorithm = common_algorithm(private, peer)?;
+        let privkey = private;
+        let pubkey = peer;
         let random = botan::RandomNumberGenerator::new_system()
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-        random.fill(&mut aead_key);
         random.fill(&mut nonce);
+        // RSA sends a random AEAD key under OAEP; X25519 sends an ephemeral public value
+        // and both sides derive the AEAD key from the shared secret.
+        let (aead_key, symmetric_key) = match (algorithm, pubkey.exchange.as_ref()) {
+            (Algorithm::Ed25519, Some(exchange)) => {
+                let ephemeral = botan::Privkey::create("Curve25519", "", &random)
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                let recipient = x25519_public_value(exchange)?;
+                let aead_key = botan::KeyAgreement::new(&ephemeral, "KDF2(SHA-256)")
+                    .and_then(|x| x.agree(32, recipient.as_slice(), nonce.as_ref()))
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                let public_value = ephemeral.key_agreement_key()
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                (aead_key, public_value)
+            }
+            _ => {
+                let mut aead_key = vec![0; 32];
+                random.fill(aead_key.as_mut_slice());
+                let encryptor = botan::Encryptor::new(&pubkey.identity, "OAEP(SHA-512)")
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                let encrypted = encryptor.encrypt(aead_key.as_slice(), &random)
+                    .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+                (aead_key, encrypted)
+            }
+        };
         let message = simd_json::to_vec(&res)?;
         let aead = botan::Cipher::new("AES-256/GCM", botan::CipherDirection::Encrypt)
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
@@ -137,23 +609,21 @@ impl Packet {
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         let message = aead.process(nonce.as_ref(), message.as_slice())
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-        let encryptor = botan::Encryptor::new(pubkey, "OAEP(SHA-512)")
-            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         let time_stamp = std::time::SystemTime::now().duration_since(UNIX_EPOCH)
             .map_err(|e| anyhow::anyhow!("{:?}", e))?.as_secs().to_string();
-        let signer = botan::Signer::new(privkey, "PKCS1v15(SHA-256)")
+        let signer = botan::Signer::new(&privkey.identity, algorithm.signature_padding())
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         signer.update(time_stamp.as_ref())
             .and_then(|_| signer.update(aead_key.as_ref()))
             .and_then(|_| signer.update(message.as_ref()))
             .and_then(|_| signer.update(nonce.as_ref()))
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
-        let aead_key = encryptor.encrypt(aead_key.as_ref(), &random)
-            .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         let signature = signer.finish(&random)
             .map_err(|e| anyhow::anyhow!("{:?}", e))?;
         Ok(Packet {
-            symmetric_key: base64.encode(aead_key.as_slice()),
+            algorithm,
+            key_id: Some(privkey.public_key()?.fingerprint()?),
+            symmetric_key: base64.encode(symmetric_key.as_slice()),
             signature: base64.encode(signature.as_slice()),
             message_block: base64.encode(message.as_slice()),
             nonce: base64.encode(nonce.as_ref()),
@@ -161,9 +631,8 @@ impl Packet {
         })
     }
 
-
-    pub async fn from_json_request_tide<T: Serialize>(res: T, key_pair: &KeyPair) -> tide::Result<Packet> {
-        Self::from_json_request(res, key_pair).await
+    pub async fn from_json_request_tide<T: Serialize>(res: T, private: &PrivateKey, peer: &PublicKey) -> tide::Result<Packet> {
+        Self::from_json_request(res, private, peer).await
             .map_err(|x| {
                 tide::Error::from_str(StatusCode::InternalServerError, x)
             })
@@ -172,45 +641,58 @@ impl Packet {
 
 #[cfg(test)]
 mod test {
-    use std::thread::sleep;
-    use std::time::Duration;
+    use xactor::Actor;
 
-    use crate::crypto::Packet;
-    use crate::server::JsonRequest;
+    use crate::api::JsonRequest;
+    use crate::crypto::{Algorithm, NonceStore, Packet, PrivateKey, StampKeeper};
 
-    extern crate test;
+    fn key() -> PrivateKey {
+        PrivateKey::generate(Algorithm::Ed25519, 0).unwrap()
+    }
 
     #[async_std::test]
     async fn no_duplicate_request() {
-        use xactor::Actor;
-        let privk = botan::Privkey::load_encrypted_pem(include_str!("/code/keys/keeper_pri.pem"), "778878ZZzz").unwrap();
-        let pubk = botan::Pubkey::load_pem(include_str!("/code/keys/keeper_pub.pem")).unwrap();
-        let mut actor = super::StampKeeper::start_default().await;
-        let message = Packet::from_json_request(
-            JsonRequest::ListPosts,
-            &privk, &pubk,
-        ).unwrap();
-        println!("{:#?}", message);
-        println!("{:?}", message.to_json_request::<crate::server::JsonRequest>(&privk, &pubk,
-                                                                               Some(&mut actor)).await.unwrap());
-        assert!(message.to_json_request::<crate::server::JsonRequest>(&privk, &pubk,
-                                                                      Some(&mut actor)).await.is_err());
+        let (server, owner) = (key(), key());
+        let keeper = StampKeeper::start_default().await.unwrap();
+        let store: &dyn NonceStore = &keeper;
+        let packet = Packet::from_json_request(JsonRequest::ContentDigests, &owner, &server.public_key().unwrap())
+            .await
+            .unwrap();
+        let request = packet.to_json_request::<JsonRequest>(&server, &owner.public_key().unwrap(), Some(store))
+            .await
+            .unwrap();
+        assert!(matches!(request, JsonRequest::ContentDigests));
+        assert!(packet.to_json_request::<JsonRequest>(&server, &owner.public_key().unwrap(), Some(store))
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn other_signers_leave_the_nonce() {
+        let (server, owner, other) = (key(), key(), key());
+        let keeper = StampKeeper::start_default().await.unwrap();
+        let store: &dyn NonceStore = &keeper;
+        let packet = Packet::from_json_request(JsonRequest::ContentDigests, &owner, &server.public_key().unwrap())
+            .await
+            .unwrap();
+        assert!(packet.to_json_request::<JsonRequest>(&server, &other.public_key().unwrap(), Some(store))
+            .await
+            .is_err());
+        assert!(packet.to_json_request::<JsonRequest>(&server, &owner.public_key().unwrap(), Some(store))
+            .await
+            .is_ok());
     }
 
     #[async_std::test]
     async fn timeout_detection() {
-        use xactor::Actor;
-        let privk = botan::Privkey::load_encrypted_pem(include_str!("/code/keys/keeper_pri.pem"), "778878ZZzz").unwrap();
-        let pubk = botan::Pubkey::load_pem(include_str!("/code/keys/keeper_pub.pem")).unwrap();
-        let mut actor = super::StampKeeper::start_default().await;
-        let message = Packet::from_json_request(
-            JsonRequest::ListPosts,
-            &privk, &pubk,
-        ).unwrap();
-        println!("{:#?}", message);
-        async_std::task::sleep(Duration::from_secs(super::TIME_OUT + 1)).await;
-        println!("{:?}", message.to_json_request::<crate::server::JsonRequest>(&privk, &pubk,
-                                                                               Some(&mut actor)).await.is_err());
+        let (server, owner) = (key(), key());
+        let mut packet = Packet::from_json_request(JsonRequest::ContentDigests, &owner, &server.public_key().unwrap())
+            .await
+            .unwrap();
+        let sent: u64 = packet.time_stamp.parse().unwrap();
+        packet.time_stamp = (sent - super::TIME_OUT - 1).to_string();
+        assert!(packet.to_json_request::<JsonRequest>(&server, &owner.public_key().unwrap(), None)
+            .await
+            .is_err());
     }
 }
-
@@ -0,0 +1,117 @@
+use std::fmt::{Display, Formatter};
+
+use anyhow::anyhow;
+use async_diesel::*;
+use diesel::prelude::*;
+use pgp::{CleartextSignedMessage, Deserializable, SignedPublicKey};
+use pgp::types::KeyTrait;
+use tide::StatusCode;
+
+use crate::ConnPool;
+use crate::model::{CommenterKey, NewCommenterKey};
+
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The content is not a well-formed armored clear-signed message.
+    MalformedArmor(String),
+    /// No key in the keyring matches the issuer of the signature.
+    UnknownKey(String),
+    /// The issuer is known but the signature does not verify.
+    BadSignature,
+    Database(String),
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::MalformedArmor(e) => write!(f, "malformed armor: {}", e),
+            VerifyError::UnknownKey(id) => write!(f, "unknown key: {}", id),
+            VerifyError::BadSignature => write!(f, "bad signature"),
+            VerifyError::Database(e) => write!(f, "keyring unavailable: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl VerifyError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            VerifyError::MalformedArmor(_) => StatusCode::BadRequest,
+            VerifyError::UnknownKey(_) | VerifyError::BadSignature => StatusCode::Unauthorized,
+            VerifyError::Database(_) => StatusCode::InternalServerError,
+        }
+    }
+}
+
+/// Parse an armored public key and collect what the keyring stores about it.
+pub fn parse_public_key(armored: &str) -> anyhow::Result<NewCommenterKey> {
+    let (key, _) = SignedPublicKey::from_string(armored)
+        .map_err(|e| anyhow!("malformed public key: {}", e))?;
+    key.verify()
+        .map_err(|e| anyhow!("invalid self signature: {}", e))?;
+    let mut key_ids = vec![hex::encode_upper(key.key_id())];
+    key_ids.extend(key.public_subkeys.iter()
+        .map(|x| hex::encode_upper(x.key_id())));
+    Ok(NewCommenterKey {
+        finger_print: hex::encode_upper(key.fingerprint()),
+        key_ids,
+        user_id: key.details.users.first()
+            .map(|x| x.id.id().to_string())
+            .unwrap_or_default(),
+        armored_key: armored.to_string(),
+    })
+}
+
+/// Verify a clear-signed message against the keyring. Returns the signed text and the
+/// fingerprint of the primary key that made the signature.
+pub async fn verify_cleartext(pool: &ConnPool, content: &str) -> Result<(String, String), VerifyError> {
+    use crate::schema::commenter_keys::dsl as k;
+    let (message, _) = CleartextSignedMessage::from_string(content)
+        .map_err(|e| VerifyError::MalformedArmor(e.to_string()))?;
+    let issuers: Vec<String> = message.signatures()
+        .iter()
+        .filter_map(|x| x.signature.issuer())
+        .map(hex::encode_upper)
+        .collect();
+    if issuers.is_empty() {
+        return Err(VerifyError::MalformedArmor("signature carries no issuer".to_string()));
+    }
+    let candidates: Vec<CommenterKey> = k::commenter_keys
+        .filter(k::key_ids.overlaps_with(issuers.clone()))
+        .load_async(pool)
+        .await
+        .map_err(|e| VerifyError::Database(e.to_string()))?;
+    if candidates.is_empty() {
+        return Err(VerifyError::UnknownKey(issuers.join(", ")));
+    }
+    for candidate in candidates {
+        let key = match SignedPublicKey::from_string(candidate.armored_key.as_str()) {
+            Ok((key, _)) => key,
+            Err(e) => {
+                log::error!("broken key {} in keyring: {}", candidate.finger_print, e);
+                continue;
+            }
+        };
+        if message.verify(&key).is_ok()
+            || key.public_subkeys.iter().any(|x| message.verify(x).is_ok()) {
+            return Ok((message.signed_text(), candidate.finger_print));
+        }
+    }
+    Err(VerifyError::BadSignature)
+}
+
+/// The identity the old gpg based verifier recorded for a signer whose key it trusted
+/// ultimately. Such rows are moved to the fingerprint once the key joins the keyring.
+pub fn legacy_finger_print(user_id: &str) -> String {
+    format!("\"{}\" [ultimate]", user_id)
+}
+
+/// Fingerprints recorded by the old gpg based verifier contain spaces; compare them in the
+/// same form as the ones produced here.
+pub fn normalize_finger_print(finger_print: &str) -> String {
+    finger_print.chars()
+        .filter(|x| !x.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
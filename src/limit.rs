@@ -0,0 +1,181 @@
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+use tide::{Request, Response, StatusCode};
+
+use crate::ServerState;
+
+/// Above this many tracked clients, buckets that have refilled completely are dropped.
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// `N/S` allows bursts of `N` requests, refilled evenly over `S` seconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Rate {
+    capacity: f64,
+    per_second: f64,
+}
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut split = s.splitn(2, '/');
+        let capacity: u32 = split.next().unwrap_or_default().trim().parse()?;
+        let seconds: u32 = split.next()
+            .ok_or_else(|| anyhow::anyhow!("rate should look like <requests>/<seconds>"))?
+            .trim()
+            .parse()?;
+        if capacity == 0 || seconds == 0 {
+            return Err(anyhow::anyhow!("rate must be positive"));
+        }
+        Ok(Rate {
+            capacity: capacity as f64,
+            per_second: capacity as f64 / seconds as f64,
+        })
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: &Rate, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.capacity);
+        self.updated = now;
+    }
+}
+
+/// Token buckets for one group of routes; clones share the same buckets.
+#[derive(Clone)]
+pub struct RateLimiter {
+    rate: Rate,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: Rate) -> Self {
+        RateLimiter {
+            rate,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Take a token for `key`, or tell how long to wait until one is available.
+    pub fn take<S: Into<String>>(&self, key: S) -> Result<(), Duration> {
+        let now = Instant::now();
+        let rate = &self.rate;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.refill(rate, now);
+                bucket.tokens < rate.capacity
+            });
+        }
+        let bucket = buckets.entry(key.into()).or_insert(Bucket {
+            tokens: rate.capacity,
+            updated: now,
+        });
+        bucket.refill(rate, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate.per_second))
+        }
+    }
+}
+
+/// The response for a rejected request; the body is filled in by `error_handle`.
+pub fn too_many_requests(wait: Duration) -> Response {
+    let mut response = Response::new(StatusCode::TooManyRequests);
+    response.insert_header("Retry-After", (wait.as_secs() + 1).to_string());
+    response.set_error(tide::Error::from_str(StatusCode::TooManyRequests, "too many requests"));
+    response
+}
+
+/// The peer address, or when the peer is a trusted proxy, the right-most address in
+/// `X-Forwarded-For` that was not added by a trusted proxy.
+pub fn client_ip(request: &Request<ServerState>) -> Option<IpAddr> {
+    let trusted_proxies = &request.state().trusted_proxies;
+    let peer = request.peer_addr()?
+        .parse::<SocketAddr>()
+        .ok()?
+        .ip();
+    if !trusted_proxies.contains(&peer) {
+        return Some(peer);
+    }
+    let forwarded: Vec<IpAddr> = match request.header("X-Forwarded-For") {
+        Some(values) => values.iter()
+            .flat_map(|x| x.as_str().split(','))
+            .filter_map(|x| x.trim().parse().ok())
+            .collect(),
+        None => return Some(peer)
+    };
+    forwarded.iter()
+        .rev()
+        .find(|x| !trusted_proxies.contains(x))
+        .or_else(|| forwarded.first())
+        .cloned()
+        .or(Some(peer))
+}
+
+/// Limits a route group by client address.
+#[derive(Clone)]
+pub struct IpRateLimit {
+    limiter: RateLimiter,
+}
+
+impl IpRateLimit {
+    pub fn new(rate: Rate) -> Self {
+        IpRateLimit {
+            limiter: RateLimiter::new(rate),
+        }
+    }
+}
+
+impl tide::Middleware<ServerState> for IpRateLimit {
+    fn handle<'a>(&'a self, request: Request<ServerState>, next: tide::Next<'a, ServerState>)
+                  -> Pin<Box<dyn Future<Output=tide::Result> + Send + 'a>> {
+        Box::pin(async move {
+            let key = client_ip(&request)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            match self.limiter.take(key) {
+                Ok(()) => next.run(request).await,
+                Err(wait) => Ok(too_many_requests(wait))
+            }
+        })
+    }
+}
+
+pub struct LimitConfig {
+    pub trusted_proxies: Vec<IpAddr>,
+    pub comment: Rate,
+    pub search: Rate,
+    pub api: Rate,
+    pub finger_print: Rate,
+    pub admin_key: Rate,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Rate, RateLimiter};
+
+    #[test]
+    fn bucket_runs_dry() {
+        let limiter = RateLimiter::new("2/60".parse::<Rate>().unwrap());
+        assert!(limiter.take("a").is_ok());
+        assert!(limiter.take("a").is_ok());
+        let wait = limiter.take("a").unwrap_err();
+        assert!(wait.as_secs() <= 30);
+        assert!(limiter.take("b").is_ok());
+    }
+}
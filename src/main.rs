@@ -13,8 +13,9 @@ use structopt::StructOpt;
 use std::sync::Arc;
 use xactor::{Actor, Addr};
 
-use crate::api::JsonResponse;
-use crate::crypto::{Packet, StampKeeper};
+use crate::crypto::{NonceStore, NonceStoreKind, PgNonceStore, PrivateKey, PublicKey, StampKeeper};
+use crate::markdown::Extensions;
+use crate::limit::{IpRateLimit, LimitConfig, RateLimiter};
 use crate::server::*;
 
 mod template;
@@ -25,9 +26,12 @@ mod model;
 mod schema;
 mod api;
 mod cli;
+mod keyring;
+mod limit;
+mod markdown;
+mod static_build;
 
 type ConnPool = Pool<ConnectionManager<PgConnection>>;
-type Conn = diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 
 #[cfg(feature = "use-snmalloc")]
 #[global_allocator]
@@ -38,19 +42,31 @@ static ALLOC: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 const PAGE_LIMIT : i64 = 5;
+/// Length of the abstracts in feeds and previews.
+const FEED_ABSTRACT : usize = 1024;
 
 #[derive(Clone)]
 pub struct ServerState {
     pool: ConnPool,
     blog_name: String,
-    stamp_keeper: Addr<StampKeeper>,
+    nonce_store: Arc<dyn NonceStore>,
     key_pair: Arc<KeyPair>,
     domain: String,
+    moderation: bool,
+    trust_approved: bool,
+    challenge_keeper: Addr<StampKeeper>,
+    challenge_secret: Arc<Vec<u8>>,
+    pow_difficulty: u32,
+    finger_print_limiter: RateLimiter,
+    admin_key_limiter: RateLimiter,
+    trusted_proxies: Arc<Vec<std::net::IpAddr>>,
+    highlight_css: Arc<String>,
+    comment_extensions: Extensions,
 }
 
 pub struct KeyPair {
-    server_private: botan::Privkey,
-    owner_public: botan::Pubkey,
+    server_private: PrivateKey,
+    owner_public: PublicKey,
 }
 
 
@@ -65,19 +81,48 @@ async fn start_server<A: AsRef<str>,
     port: u16,
     web_root: B,
     pool: ConnPool,
-    stamp_keeper: Addr<StampKeeper>,
-    server_private: botan::Privkey,
-    owner_public: botan::Pubkey,
+    nonce_store: Arc<dyn NonceStore>,
+    server_private: PrivateKey,
+    owner_public: PublicKey,
     blog_name: String,
     domain: String,
+    moderation: bool,
+    trust_approved: bool,
+    pow_difficulty: u32,
+    limits: LimitConfig,
+    highlight_css: String,
+    comment_extensions: Extensions,
 ) -> anyhow::Result<()> {
+    let key_pair = Arc::new(KeyPair { server_private, owner_public });
+    key_pair.algorithm()?;
+    let mut challenge_secret = vec![0; 32];
+    botan::RandomNumberGenerator::new_system()
+        .and_then(|x| x.fill(challenge_secret.as_mut_slice()))
+        .map_err(|x| anyhow!("{:?}", x))?;
+    let challenge_keeper =
+        StampKeeper::with_time_out(crate::crypto::CHALLENGE_TIME_OUT)
+            .start()
+            .await?;
     let mut http_server = tide::with_state(ServerState {
         pool,
         blog_name,
-        stamp_keeper,
-        key_pair: Arc::new(KeyPair { server_private, owner_public }),
+        nonce_store,
+        key_pair,
         domain,
+        moderation,
+        trust_approved,
+        challenge_keeper,
+        challenge_secret: Arc::new(challenge_secret),
+        pow_difficulty,
+        finger_print_limiter: RateLimiter::new(limits.finger_print),
+        admin_key_limiter: RateLimiter::new(limits.admin_key),
+        trusted_proxies: Arc::new(limits.trusted_proxies),
+        highlight_css: Arc::new(highlight_css),
+        comment_extensions,
     });
+    let comment_limit = IpRateLimit::new(limits.comment);
+    let search_limit = IpRateLimit::new(limits.search);
+    let api_limit = IpRateLimit::new(limits.api);
     http_server.at("/static").serve_dir(web_root.as_ref().join("static"))?;
     http_server.at("/posts").strip_prefix().get(serve_posts);
     http_server.at("/post").strip_prefix().get(serve_post);
@@ -85,17 +130,18 @@ async fn start_server<A: AsRef<str>,
     http_server.at("/tag").strip_prefix().get(serve_tag);
     http_server.at("/tags").get(serve_tags);
     http_server.at("/lucky").get(serve_lucky);
-    http_server.at("/search").post(handle_search);
+    http_server.at("/search").with(search_limit).post(handle_search);
     http_server.at("/raw/comment").strip_prefix().get(serve_comment_raw);
     http_server.at("/raw/post").strip_prefix().get(serve_post_raw);
     http_server.at("/raw/page").strip_prefix().get(serve_page_raw);
-    http_server.at("/comment/submit").post(handle_comment);
+    http_server.at("/comment/submit").with(comment_limit.clone()).post(handle_comment);
     http_server.at("/comment/remove").strip_prefix().get(remove_comment);
-    http_server.at("/comment/remove").post(handle_remove_comment);
+    http_server.at("/comment/remove").with(comment_limit).post(handle_remove_comment);
     http_server.at("/rss.xml").get(handle_rss);
     http_server.at("/atom.xml").get(handle_atom);
     http_server.at("/sitemap.xml").get(handle_sitemap);
-    http_server.at("/api").post(handle_api);
+    http_server.at("/highlight.css").get(serve_highlight_css);
+    http_server.at("/api").with(api_limit).post(handle_api);
     http_server.at("/").get(index);
     http_server.with(tide::utils::After(error_handle));
     http_server.with(tide_compress::CompressMiddleware::new());
@@ -122,9 +168,22 @@ async fn main() -> anyhow::Result<()> {
             postgres,
             blog_name,
             web_root,
-            domain
+            domain,
+            moderation,
+            trust_approved,
+            pow_difficulty,
+            trusted_proxy,
+            comment_rate,
+            search_rate,
+            api_rate,
+            finger_print_rate,
+            admin_key_rate,
+            nonce_store,
+            highlight_theme,
+            comment_extensions,
         } => {
             tide::log::start();
+            let highlight_css = markdown::highlight_css(highlight_theme.as_str())?;
             let manager =
                 diesel::r2d2::ConnectionManager::<diesel::pg::PgConnection>
                 ::new(postgres);
@@ -134,27 +193,36 @@ async fn main() -> anyhow::Result<()> {
                 std::fs::read_to_string(server_private_key.as_path())?;
             let private_key = if encrypted_server_private {
                 let password = rpassword::prompt_password_stdout("please input password: ")?;
-                botan::Privkey::load_encrypted_pem(private_key_file.as_str(), password.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?
+                PrivateKey::load(private_key_file.as_str(), Some(password.as_str()))?
             } else {
-                botan::Privkey::load_pem(private_key_file.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?
+                PrivateKey::load(private_key_file.as_str(), None)?
             };
             let public_key_file =
                 std::fs::read_to_string(owner_public_key.as_path())?;
             let public_key =
-                botan::Pubkey::load_pem(public_key_file.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?;
-            let stamp_keeper =
-                StampKeeper::start_default().
-                    await?;
+                PublicKey::load(public_key_file.as_str())?;
+            let nonce_store: Arc<dyn NonceStore> = match nonce_store {
+                NonceStoreKind::Memory => Arc::new(StampKeeper::start_default().await?),
+                NonceStoreKind::Postgres => Arc::new(PgNonceStore::start(pool.clone())),
+            };
             start_server(listen_address,
                          port,
                          web_root,
                          pool,
-                         stamp_keeper,
+                         nonce_store,
                          private_key,
-                         public_key, blog_name, domain).await
+                         public_key, blog_name, domain,
+                         moderation, trust_approved, pow_difficulty,
+                         LimitConfig {
+                             trusted_proxies: trusted_proxy,
+                             comment: comment_rate,
+                             search: search_rate,
+                             api: api_rate,
+                             finger_print: finger_print_rate,
+                             admin_key: admin_key_rate,
+                         },
+                         highlight_css,
+                         comment_extensions).await
         }
         crate::cli::Command::Client {
             server_address,
@@ -165,78 +233,72 @@ async fn main() -> anyhow::Result<()> {
             command
         } => {
             pretty_env_logger::try_init_timed_custom_env("BLOG_CLIENT_LOG")?;
-            let is_raw = command.is_raw_content();
-            let request = command.into_json_request()?;
             let private_key_file =
                 std::fs::read_to_string(private_key.as_path())?;
             let private_key = if encrypted_private_key {
                 let password = rpassword::prompt_password_stdout("please input password: ")?;
-                botan::Privkey::load_encrypted_pem(private_key_file.as_str(), password.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?
+                PrivateKey::load(private_key_file.as_str(), Some(password.as_str()))?
             } else {
-                botan::Privkey::load_pem(private_key_file.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?
+                PrivateKey::load(private_key_file.as_str(), None)?
             };
             let public_key_file =
                 std::fs::read_to_string(public_key.as_path())?;
             let public_key =
-                botan::Pubkey::load_pem(public_key_file.as_str())
-                    .map_err(|x| anyhow!("{:?}", x))?;
+                PublicKey::load(public_key_file.as_str())?;
             let key_pair = KeyPair {
                 server_private: private_key,
                 owner_public: public_key,
             };
-            let packet = Packet::from_json_request(request, &key_pair).await?;
-            let response = surf::Client::new()
-                .post(format!("{}:{}/api", server_address, port))
-                .body(simd_json::to_string(&packet)?)
-                .await
-                .map_err(|x| anyhow!("{}", x))?
-                .body_string()
-                .await
-                .map_err(|x| anyhow!("{}", x))
-                .and_then(|mut x| simd_json::from_str::<Packet>(x.as_mut_str())
-                    .map_err(|x| x.into()))?;
-            let real_response: JsonResponse = response
-                .to_json_request(&key_pair, None)
-                .await?;
-            if !is_raw {
-                crate::utils::to_table(&real_response)?.printstd();
-            } else {
-                let content = real_response.get_raw_content()?;
-                println!("{}", content);
-            }
-            Ok(())
+            key_pair.algorithm()?;
+            let remote = crate::cli::client::Remote {
+                address: format!("{}:{}/api", server_address, port),
+                key_pair,
+            };
+            command.run(&remote).await
         }
         crate::cli::Command::KeyGen {
             private_key_path,
             public_key_path,
             encryption,
-            key_length
+            key_length,
+            algorithm
         } => {
-            utils::confirm(format!("generate the key pair ({:?},{:?}, encryption: {})",
-                                   private_key_path, public_key_path, encryption))?;
-            let random = botan::RandomNumberGenerator::new_system()
-                .map_err(|x| anyhow!("{:?}", x))?;
-            let private_key = botan::Privkey::create(
-                "RSA",
-                key_length.to_string().as_str(),
-                &random,
-            ).map_err(|x| anyhow!("{:?}", x))?;
+            utils::confirm(format!("generate the {:?} key pair ({:?},{:?}, encryption: {})",
+                                   algorithm, private_key_path, public_key_path, encryption))?;
+            let private_key = PrivateKey::generate(algorithm, key_length)?;
             let private_key_pem = if encryption {
                 let password = rpassword::prompt_password_stdout("please input password: ")?;
-                private_key.pem_encode_encrypted(password.as_str(), &random)
-                    .map_err(|x| anyhow!("{:?}", x))?
+                private_key.pem_encode(Some(password.as_str()))?
             } else {
-                private_key.pem_encode()
-                    .map_err(|x| anyhow!("{:?}", x))?
+                private_key.pem_encode(None)?
             };
-            let public_key_pem = private_key.pubkey()
-                .and_then(|x| x.pem_encode())
-                .map_err(|x| anyhow!("{:?}", x))?;
+            let public_key_pem = private_key.public_key()?.pem_encode()?;
             std::fs::write(private_key_path, private_key_pem)?;
             std::fs::write(public_key_path, public_key_pem)?;
             Ok(())
         }
+        crate::cli::Command::StaticBuild {
+            postgres,
+            blog_name,
+            web_root,
+            domain,
+            output,
+            highlight_theme,
+            comment_extensions,
+        } => {
+            pretty_env_logger::try_init_timed_custom_env("BLOG_CLIENT_LOG")?;
+            let manager =
+                diesel::r2d2::ConnectionManager::<diesel::pg::PgConnection>
+                ::new(postgres);
+            let pool =
+                diesel::r2d2::Pool::new(manager)?;
+            let highlight_css = markdown::highlight_css(highlight_theme.as_str())?;
+            let written = crate::static_build::StaticSite::new(&pool, blog_name.as_str(), domain.as_str(),
+                                                               output.as_path(), comment_extensions)
+                .build(web_root, highlight_css)
+                .await?;
+            println!("wrote {} files to {:?}", written, output);
+            Ok(())
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,602 @@
+//! Markdown rendering with math. Math is found in the source before pulldown-cmark runs, skipping
+//! code spans, code blocks and raw html, and swapped for placeholders that markdown leaves alone:
+//!
+//! - `$..$` and `\(..\)` are inline math; an inline `$` has to be followed by a non-space, and
+//!   the closing one preceded by a non-space and not followed by a digit, so `$5 or $10` is text;
+//! - `$$..$$` and `\[..\]` are display math;
+//! - `\$` stays a literal dollar sign.
+//!
+//! Fenced code blocks in a language syntect knows are highlighted into spans with css classes, see
+//! [`highlight_css`] for the colours. Headings get `id` anchors made from their text, and
+//! [`render_with_contents`] hands them out as a table of contents.
+//!
+//! Posts and pages use the GFM tables, footnotes, strikethrough and task lists; footnotes are moved
+//! into a numbered list at the end with links back to their references. Comments get the
+//! [`Extensions`] passed to [`render_safe`].
+
+use std::ops::Range;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use katex::Opts;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag};
+use syntect::highlighting::ThemeSet;
+use syntect::html::{ClassedHTMLGenerator, ClassStyle, css_for_theme_with_class_style};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::utils::slugify;
+
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+/// Version of the html produced for the same markdown, stored with cached html; bump it whenever
+/// the output changes so that html rendered by an older version is no longer served.
+pub const RENDERER_VERSION: i32 = 3;
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+#[derive(Debug, Eq, PartialEq)]
+struct MathSpan {
+    /// The whole span in the source, delimiters included.
+    range: Range<usize>,
+    tex: Range<usize>,
+    display: bool,
+}
+
+/// A heading of a document and the `id` of its html element.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Heading {
+    pub level: u32,
+    pub id: String,
+    pub title: String,
+}
+
+/// Hands out heading ids; an id that is already taken gets `-1`, `-2`, ... appended.
+#[derive(Default)]
+struct Anchors {
+    used: hashbrown::HashSet<String>,
+}
+
+impl Anchors {
+    fn assign(&mut self, title: &str) -> String {
+        let base = match slugify(title) {
+            x if x.is_empty() => "section".to_string(),
+            x => x
+        };
+        let mut id = base.clone();
+        let mut suffix = 0;
+        while self.used.contains(&id) {
+            suffix += 1;
+            id = format!("{}-{}", base, suffix);
+        }
+        self.used.insert(id.clone());
+        id
+    }
+}
+
+/// A set of markdown extensions, written as a comma separated list of `tables`, `footnotes`,
+/// `strikethrough` and `tasklists`, or as `none`.
+#[derive(Copy, Clone, Debug)]
+pub struct Extensions(Options);
+
+impl FromStr for Extensions {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut options = Options::empty();
+        for name in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            options |= match name {
+                "tables" => Options::ENABLE_TABLES,
+                "footnotes" => Options::ENABLE_FOOTNOTES,
+                "strikethrough" => Options::ENABLE_STRIKETHROUGH,
+                "tasklists" => Options::ENABLE_TASKLISTS,
+                "none" => Options::empty(),
+                _ => return Err(anyhow!("unknown markdown extension {}", name))
+            };
+        }
+        Ok(Extensions(options))
+    }
+}
+
+/// Extensions of posts and pages.
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+/// Source ranges that math must not be looked for in.
+fn code_ranges(source: &str, options: Options) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Parser::new_ext(source, options)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Code(_) | Event::Html(_) | Event::Start(Tag::CodeBlock(_)) => Some(range),
+            _ => None
+        })
+        .collect();
+    ranges.sort_by_key(|x| x.start);
+    ranges
+}
+
+/// Position of the first `pattern` in `bytes[from..limit]` that is not escaped by a backslash.
+fn find_closing(bytes: &[u8], from: usize, limit: usize, pattern: &[u8]) -> Option<usize> {
+    let mut i = from;
+    while i + pattern.len() <= limit {
+        if bytes[i..].starts_with(pattern) {
+            return Some(i);
+        }
+        i += if bytes[i] == b'\\' { 2 } else { 1 };
+    }
+    None
+}
+
+fn find_inline_closing(bytes: &[u8], from: usize, limit: usize) -> Option<usize> {
+    let mut i = from;
+    while i < limit {
+        match bytes[i] {
+            b'\\' => {
+                i += 2;
+                continue;
+            }
+            // inline math does not run over a paragraph break
+            b'\n' if bytes[i + 1..limit].iter()
+                .take_while(|x| **x != b'\n')
+                .all(u8::is_ascii_whitespace) => return None,
+            b'$' if !bytes[i - 1].is_ascii_whitespace()
+                && !bytes.get(i + 1).map(u8::is_ascii_digit).unwrap_or(false) => return Some(i),
+            _ => ()
+        }
+        i += 1;
+    }
+    None
+}
+
+fn find_math(source: &str, options: Options) -> Vec<MathSpan> {
+    let bytes = source.as_bytes();
+    let mut code = code_ranges(source, options).into_iter().peekable();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        while code.peek().map(|x| x.end <= i).unwrap_or(false) {
+            code.next();
+        }
+        let limit = match code.peek() {
+            Some(x) if x.start <= i => {
+                i = x.end;
+                continue;
+            }
+            Some(x) => x.start,
+            None => bytes.len(),
+        };
+        let found = match (bytes[i], bytes.get(i + 1)) {
+            (b'\\', Some(b'(')) => find_closing(bytes, i + 2, limit, b"\\)")
+                .map(|end| (i + 2..end, end + 2, false)),
+            (b'\\', Some(b'[')) => find_closing(bytes, i + 2, limit, b"\\]")
+                .map(|end| (i + 2..end, end + 2, true)),
+            (b'$', Some(b'$')) => find_closing(bytes, i + 2, limit, b"$$")
+                .map(|end| (i + 2..end, end + 2, true)),
+            (b'$', Some(x)) if !x.is_ascii_whitespace() => find_inline_closing(bytes, i + 1, limit)
+                .map(|end| (i + 1..end, end + 1, false)),
+            _ => None
+        };
+        match found {
+            Some((tex, end, display)) => {
+                spans.push(MathSpan { range: i..end, tex, display });
+                i = end;
+            }
+            // skip the escaped character, or both dollars of an unmatched `$$`
+            None if bytes[i] == b'\\' => i += 2,
+            None if bytes[i] == b'$' && bytes.get(i + 1) == Some(&b'$') => i += 2,
+            None => i += 1,
+        }
+    }
+    spans
+}
+
+fn escape_html(text: &str) -> String {
+    let mut buffer = String::with_capacity(text.len());
+    for i in text.chars() {
+        match i {
+            '&' => buffer.push_str("&amp;"),
+            '<' => buffer.push_str("&lt;"),
+            '>' => buffer.push_str("&gt;"),
+            '"' => buffer.push_str("&quot;"),
+            _ => buffer.push(i),
+        }
+    }
+    buffer
+}
+
+fn render_math(source: &str, span: &MathSpan) -> String {
+    let opts = Opts::builder()
+        .display_mode(span.display)
+        .build()
+        .unwrap();
+    katex::render_with_opts(&source[span.tex.clone()], opts)
+        .unwrap_or_else(|e| escape_html(format!("[MATH ERROR: {}]", e).as_str()))
+}
+
+fn placeholder(index: usize) -> String {
+    format!("{}{}{}", PLACEHOLDER_START, index, PLACEHOLDER_END)
+}
+
+/// The source with every math span swapped for a numbered placeholder. Placeholder characters
+/// that were in the source already are replaced, so that every placeholder left is one of ours.
+fn with_placeholders(source: &str, spans: &[MathSpan]) -> String {
+    let mut buffer = String::with_capacity(source.len());
+    let mut last = 0;
+    let push_text = |buffer: &mut String, text: &str| buffer.extend(text.chars()
+        .map(|x| if x == PLACEHOLDER_START || x == PLACEHOLDER_END { '\u{FFFD}' } else { x }));
+    for (index, span) in spans.iter().enumerate() {
+        push_text(&mut buffer, &source[last..span.range.start]);
+        buffer.push_str(placeholder(index).as_str());
+        last = span.range.end;
+    }
+    push_text(&mut buffer, &source[last..]);
+    buffer
+}
+
+/// Put `replace(index)` where the placeholders are; `text` is applied to everything else, including
+/// placeholder look-alikes that were in the source already.
+fn fill_placeholders<F, T>(rendered: &str, mut replace: F, text: T) -> String
+    where F: FnMut(usize) -> Option<String>, T: Fn(&str) -> String {
+    let mut buffer = String::with_capacity(rendered.len());
+    let mut rest = rendered;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let end = match rest[start..].find(PLACEHOLDER_END) {
+            Some(x) => start + x,
+            None => break
+        };
+        buffer.push_str(text(&rest[..start]).as_str());
+        match rest[start + PLACEHOLDER_START.len_utf8()..end].parse().ok().and_then(&mut replace) {
+            Some(replaced) => buffer.push_str(replaced.as_str()),
+            None => buffer.push_str(text(&rest[start..end + PLACEHOLDER_END.len_utf8()]).as_str()),
+        }
+        rest = &rest[end + PLACEHOLDER_END.len_utf8()..];
+    }
+    buffer.push_str(text(rest).as_str());
+    buffer
+}
+
+/// Split a text event at the placeholders, turning each into the event `math` gives it.
+fn split_math<'a, F>(text: &str, math: &mut F) -> Vec<Event<'a>>
+    where F: FnMut(usize) -> Option<Event<'static>> {
+    let mut events = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER_START) {
+        let end = match rest[start..].find(PLACEHOLDER_END) {
+            Some(x) => start + x + PLACEHOLDER_END.len_utf8(),
+            None => break
+        };
+        match rest[start + PLACEHOLDER_START.len_utf8()..end - PLACEHOLDER_END.len_utf8()].parse().ok().and_then(&mut *math) {
+            Some(event) => {
+                if start > 0 {
+                    events.push(Event::Text(CowStr::from(rest[..start].to_string())));
+                }
+                events.push(event);
+            }
+            None => events.push(Event::Text(CowStr::from(rest[..end].to_string()))),
+        }
+        rest = &rest[end..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(CowStr::from(rest.to_string())));
+    }
+    events
+}
+
+/// Put the math back into the document: text becomes what `math` makes of it, while image
+/// descriptions, link destinations and titles, which end up in attributes, get the TeX source.
+fn fill_math<'a, I, F>(events: I, source: &'a str, spans: &'a [MathSpan], mut math: F) -> impl Iterator<Item=Event<'a>>
+    where I: Iterator<Item=Event<'a>>, F: FnMut(usize) -> Option<Event<'static>> {
+    let plain = move |text: &str| CowStr::from(fill_placeholders(
+        text, |index| spans.get(index).map(|x| source[x.range.clone()].to_string()), ToString::to_string));
+    let mut image_depth = 0;
+    events.flat_map(move |event| match event {
+        Event::Start(Tag::Image(kind, url, title)) => {
+            image_depth += 1;
+            vec![Event::Start(Tag::Image(kind, plain(&url), plain(&title)))]
+        }
+        Event::End(Tag::Image(..)) => {
+            image_depth -= 1;
+            vec![event]
+        }
+        Event::Start(Tag::Link(kind, url, title)) => vec![Event::Start(Tag::Link(kind, plain(&url), plain(&title)))],
+        Event::Text(text) if image_depth > 0 => vec![Event::Text(plain(&text))],
+        Event::Text(text) if text.contains(PLACEHOLDER_START) => split_math(&text, &mut math),
+        _ => vec![event]
+    })
+}
+
+/// Replace fenced code blocks in a known language by highlighted html. Other blocks, like the
+/// `klipse-*` ones that are run in the browser, are left to pulldown-cmark.
+fn highlight<'a, I: Iterator<Item=Event<'a>>>(events: I) -> impl Iterator<Item=Event<'a>> {
+    let mut block: Option<(ClassedHTMLGenerator<'static>, String)> = None;
+    events.filter_map(move |event| match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) => {
+            let language = info.split_whitespace().next().unwrap_or_default().to_string();
+            match SYNTAXES.find_syntax_by_token(language.as_str()) {
+                Some(syntax) if !language.is_empty() => {
+                    let generator = ClassedHTMLGenerator::new_with_class_style(
+                        syntax, &SYNTAXES, ClassStyle::Spaced);
+                    block = Some((generator, language));
+                    None
+                }
+                _ => Some(event)
+            }
+        }
+        Event::Text(text) if block.is_some() => {
+            if let Some((generator, _)) = block.as_mut() {
+                for line in LinesWithEndings::from(&text) {
+                    generator.parse_html_for_line_which_includes_newline(line);
+                }
+            }
+            None
+        }
+        Event::End(Tag::CodeBlock(_)) if block.is_some() => block.take()
+            .map(|(generator, language)| Event::Html(CowStr::from(format!(
+                "<pre class=\"code\"><code class=\"language-{}\">{}</code></pre>\n",
+                escape_html(language.as_str()), generator.finalize())))),
+        _ => Some(event)
+    })
+}
+
+/// Stylesheet giving the highlighted code the colours of a syntect theme, like `InspiredGitHub`
+/// or `base16-ocean.dark`.
+pub fn highlight_css(theme: &str) -> anyhow::Result<String> {
+    let themes = ThemeSet::load_defaults();
+    let found = themes.themes.get(theme)
+        .ok_or_else(|| anyhow!("unknown highlight theme {}, available: {}", theme,
+                               themes.themes.keys().cloned().collect::<Vec<_>>().join(", ")))?;
+    Ok(css_for_theme_with_class_style(found, ClassStyle::Spaced))
+}
+
+/// Headings of the source with placeholders in `marked`, titles in plain text with the TeX of the math.
+fn headings(source: &str, spans: &[MathSpan], marked: &str, options: Options) -> Vec<Heading> {
+    let mut anchors = Anchors::default();
+    let mut found = Vec::new();
+    let mut current: Option<(u32, String)> = None;
+    for event in Parser::new_ext(marked, options) {
+        match event {
+            Event::Start(Tag::Heading(level)) => current = Some((level, String::new())),
+            Event::Text(text) | Event::Code(text) => if let Some((_, title)) = current.as_mut() {
+                title.push_str(&text);
+            },
+            Event::End(Tag::Heading(_)) => if let Some((level, title)) = current.take() {
+                let title = fill_placeholders(title.as_str(),
+                                              |index| spans.get(index).map(|x| source[x.tex.clone()].to_string()),
+                                              ToString::to_string);
+                found.push(Heading { level, id: anchors.assign(title.trim()), title });
+            },
+            _ => ()
+        }
+    }
+    found
+}
+
+fn anchor_headings<'a, I: Iterator<Item=Event<'a>>>(events: I, headings: Vec<Heading>) -> impl Iterator<Item=Event<'a>> {
+    let mut ids = headings.into_iter().map(|x| x.id);
+    events.map(move |event| match event {
+        Event::Start(Tag::Heading(level)) => match ids.next() {
+            Some(id) => Event::Html(CowStr::from(format!("<h{} id=\"{}\">", level, escape_html(id.as_str())))),
+            None => event
+        },
+        _ => event
+    })
+}
+
+/// Move footnote definitions into a list at the end of the document, numbered in the order they
+/// are first referenced, each with links back to its references.
+fn collect_footnotes<'a, I: Iterator<Item=Event<'a>>>(events: I) -> Vec<Event<'a>> {
+    let mut body = Vec::new();
+    let mut definitions: Vec<(CowStr<'a>, Vec<Event<'a>>)> = Vec::new();
+    let mut current: Option<(CowStr<'a>, Vec<Event<'a>>)> = None;
+    // labels by number, and how many times each has been referenced
+    let mut numbered: Vec<(CowStr<'a>, usize)> = Vec::new();
+    for event in events {
+        let event = match event {
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                current = Some((label, Vec::new()));
+                continue;
+            }
+            Event::End(Tag::FootnoteDefinition(_)) => {
+                definitions.extend(current.take());
+                continue;
+            }
+            Event::FootnoteReference(label) => {
+                let number = match numbered.iter().position(|x| x.0 == label) {
+                    Some(index) => index + 1,
+                    None => {
+                        numbered.push((label, 0));
+                        numbered.len()
+                    }
+                };
+                numbered[number - 1].1 += 1;
+                Event::Html(CowStr::from(format!(
+                    "<sup class=\"footnote-reference\" id=\"fnref-{0}-{1}\"><a href=\"#fn-{0}\">{0}</a></sup>",
+                    number, numbered[number - 1].1)))
+            }
+            other => other
+        };
+        match current.as_mut() {
+            Some((_, content)) => content.push(event),
+            None => body.push(event),
+        }
+    }
+    if numbered.is_empty() {
+        return body;
+    }
+    body.push(Event::Html(CowStr::from("<section class=\"footnotes\">\n<ol>\n")));
+    for (index, (label, references)) in numbered.into_iter().enumerate() {
+        let number = index + 1;
+        let back_links: String = (1..=references)
+            .map(|x| format!(" <a href=\"#fnref-{}-{}\" class=\"footnote-back-link\">↩</a>", number, x))
+            .collect();
+        body.push(Event::Html(CowStr::from(format!("<li id=\"fn-{}\">\n", number))));
+        let mut content = definitions.iter()
+            .position(|x| x.0 == label)
+            .map(|x| definitions.swap_remove(x).1)
+            .unwrap_or_default();
+        // the links go at the end of the last paragraph rather than on a line of their own
+        let last = match content.last() {
+            Some(Event::End(Tag::Paragraph)) => content.pop(),
+            _ => None
+        };
+        content.push(Event::Html(CowStr::from(back_links)));
+        content.extend(last);
+        body.extend(content);
+        body.push(Event::Html(CowStr::from("</li>\n")));
+    }
+    body.push(Event::Html(CowStr::from("</ol>\n</section>\n")));
+    body
+}
+
+/// Html of the source and its headings in order, where the math spans found in text become the
+/// events `math` gives them.
+fn render_with<F>(source: &str, spans: &[MathSpan], options: Options, math: F) -> (String, Vec<Heading>)
+    where F: FnMut(usize) -> Option<Event<'static>> {
+    let marked = with_placeholders(source, spans);
+    let anchors = headings(source, spans, marked.as_str(), options);
+    let events = fill_math(Parser::new_ext(marked.as_str(), options), source, spans, math);
+    let events = anchor_headings(events, anchors.clone());
+    let mut buffer = String::with_capacity(marked.len() * 2);
+    pulldown_cmark::html::push_html(&mut buffer, collect_footnotes(highlight(events)).into_iter());
+    (buffer, anchors)
+}
+
+/// Render trusted markdown, as written by the blog owner, along with its headings in order.
+pub fn render_with_contents(source: &str) -> (String, Vec<Heading>) {
+    let spans = find_math(source, options());
+    render_with(source, spans.as_slice(), options(),
+                |index| spans.get(index).map(|x| Event::Html(CowStr::from(render_math(source, x)))))
+}
+
+/// Render trusted markdown, as written by the blog owner.
+pub fn render(source: &str) -> String {
+    render_with_contents(source).0
+}
+
+/// Render markdown from readers with the given extensions. The math is kept as placeholders in
+/// text until the html is sanitized, so that the KaTeX markup keeps its classes and styles.
+pub fn render_safe(source: &str, extensions: Extensions) -> String {
+    let options = extensions.0;
+    let spans = find_math(source, options);
+    let (html, _) = render_with(source, spans.as_slice(), options,
+                                |index| Some(Event::Text(CowStr::from(placeholder(index)))));
+    let html = ammonia::Builder::default()
+        // the disabled checkboxes of task lists
+        .add_tags(&["input"])
+        .add_tag_attributes("input", &["type", "checked", "disabled"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            ("input", "type") if value != "checkbox" => None,
+            _ => Some(value.into())
+        })
+        .clean(html.as_str())
+        .to_string();
+    fill_placeholders(html.as_str(), |index| spans.get(index).map(|x| render_math(source, x)), ToString::to_string)
+}
+
+/// Plain text from the start of the document, at least `limit` bytes long when there is enough,
+/// with list items, line breaks and paragraphs kept as lines. Math is never cut in half.
+fn abstract_of<F, T>(source: &str, limit: usize, replace: F, text: T) -> String
+    where F: Fn(&str, &MathSpan) -> String, T: Fn(&str) -> String {
+    let spans = find_math(source, options());
+    let marked = with_placeholders(source, spans.as_slice());
+    let mut abstract_content = String::new();
+    let mut indent = 0;
+    let mut in_math = false;
+    'outer: for event in Parser::new_ext(marked.as_str(), options()) {
+        match event {
+            Event::Text(content) => {
+                for i in content.chars() {
+                    if abstract_content.len() >= limit && !in_math {
+                        break 'outer;
+                    }
+                    abstract_content.push(i);
+                    match i {
+                        PLACEHOLDER_START => in_math = true,
+                        PLACEHOLDER_END => in_math = false,
+                        _ => ()
+                    }
+                }
+            }
+            Event::Start(Tag::Item) => {
+                indent += 1;
+                abstract_content.push_str(&" ".repeat(indent));
+                abstract_content.push_str("- ");
+            }
+            Event::End(Tag::Item) => {
+                indent -= 1;
+                abstract_content.push('\n');
+            }
+            Event::HardBreak | Event::SoftBreak => abstract_content.push('\n'),
+            Event::End(Tag::Heading(..)) | Event::End(Tag::Paragraph) => abstract_content.push('\n'),
+            _ => continue
+        }
+    }
+    abstract_content.push_str("...");
+    fill_placeholders(abstract_content.as_str(), |index| spans.get(index).map(|x| replace(source, x)), text)
+}
+
+/// Plain text abstract keeping the TeX source of the math, for pages that typeset it in the browser;
+/// dollars outside math are escaped again so that MathJax leaves them alone.
+pub fn abstract_text(source: &str, limit: usize) -> String {
+    abstract_of(source, limit,
+                |source, span| source[span.range.clone()].to_string(),
+                |x| x.replace('$', "\\$"))
+}
+
+/// Html abstract with the math rendered, for feeds.
+pub fn render_abstract(source: &str, limit: usize) -> String {
+    abstract_of(source, limit, render_math, |x| escape_html(x).replace('\n', "<br/>"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{find_math, options, render, render_safe, render_with_contents};
+
+    fn math(source: &str) -> Vec<&str> {
+        find_math(source, options()).into_iter()
+            .map(|x| &source[x.tex])
+            .collect()
+    }
+
+    #[test]
+    fn finds_all_delimiters() {
+        assert_eq!(math("$a$, $$b$$, \\(c\\) and \\[d\\]"), vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn skips_code_and_escapes() {
+        assert_eq!(math("`$a$` and\n\n```\n$$b$$\n```\n"), Vec::<&str>::new());
+        assert_eq!(math("costs \\$5, $x$ costs \\$6"), vec!["x"]);
+        assert_eq!(math("$5 or $10"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn deduplicates_heading_ids() {
+        let ids: Vec<String> = render_with_contents("# Intro\n## Intro\n## Intro 1\n# 你好\n# ?").1
+            .into_iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec!["intro", "intro-1", "intro-1-1", "你好", "section"]);
+    }
+
+    #[test]
+    fn numbers_footnotes_by_reference() {
+        let html = render("a[^b] c[^a]\n\n[^a]: first\n\n[^b]: second\n");
+        assert!(html.contains("<a href=\"#fn-1\">1</a></sup> c"));
+        assert!(html.find("<li id=\"fn-1\">\n<p>second").unwrap() < html.find("<li id=\"fn-2\">\n<p>first").unwrap());
+        assert!(html.contains("<a href=\"#fnref-1-1\" class=\"footnote-back-link\">↩</a></p>"));
+    }
+
+    #[test]
+    fn keeps_tex_in_attributes() {
+        let html = render("![$x$ and `y`](a.png \"$y$\") [b](c \"\\(z\\)\")");
+        assert!(html.contains("alt=\"$x$ and y\""));
+        assert!(html.contains("title=\"$y$\""));
+        assert!(html.contains("title=\"\\(z\\)\""));
+        let html = render_safe("[a](b \"\u{E000}0\u{E001}\") $$x$$", "none".parse().unwrap());
+        assert!(html.contains("title=\"\u{FFFD}0\u{FFFD}\""));
+    }
+}
@@ -1,14 +1,16 @@
 use http_types::{Status, StatusCode};
 
-use crate::Conn;
-use crate::schema::{comments, pages, posts};
+use crate::schema::{admin_keys, audit_logs, commenter_keys, comments, pages, posts, revisions};
 use diesel::pg::Pg;
 use diesel::{RunQueryDsl, QueryDsl};
 use crate::PAGE_LIMIT;
-use regex::Captures;
-use katex::Opts;
+use crate::markdown::{Extensions, Heading};
+use std::io::Write;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Text;
 
-#[derive(diesel::QueryableByName, diesel::Queryable, diesel::Associations, diesel::Identifiable, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(diesel::QueryableByName, diesel::Queryable, diesel::Associations, diesel::Identifiable, Insertable, Debug, serde::Serialize, serde::Deserialize)]
 #[table_name="posts"]
 pub struct Post {
     pub id: i32,
@@ -17,80 +19,136 @@ pub struct Post {
     pub update_date: chrono::NaiveDateTime,
     pub tags: Vec<String>,
     pub content: String,
+    pub status: PostStatus,
+    pub slug: String,
+    /// Whether a long post shows its table of contents.
+    #[serde(default = "default_toc")]
+    pub toc: bool,
 }
-const BLOCK_MATH : &str = r#"\$\$((?:\n|.)*?)\$\$"#;
-const INLINE_MATH : &str = r#"\$(.*?)\$"#;
+
+pub fn default_toc() -> bool {
+    true
+}
+
+/// Posts with at most this many headings are short enough to go without a table of contents.
+const TOC_MIN_HEADINGS: usize = 3;
+
+/// Publishing state of a post. A post is visible to readers only when it is not a draft
+/// and its `public_date` has already passed, so scheduled posts go live on their own.
+#[derive(diesel::AsExpression, diesel::FromSqlRow, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[sql_type = "Text"]
+pub enum PostStatus {
+    Draft,
+    Scheduled,
+    Published,
+}
+
+impl PostStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostStatus::Draft => "draft",
+            PostStatus::Scheduled => "scheduled",
+            PostStatus::Published => "published",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for PostStatus {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Text, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for PostStatus {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "draft" => Ok(PostStatus::Draft),
+            "scheduled" => Ok(PostStatus::Scheduled),
+            "published" => Ok(PostStatus::Published),
+            other => Err(format!("unrecognized post status: {}", other).into())
+        }
+    }
+}
+
+/// What an admin key may do through the API: editors create and update posts and pages but
+/// cannot delete them, moderators manage comments, owners can do everything including managing
+/// admin keys.
+#[derive(diesel::AsExpression, diesel::FromSqlRow, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[sql_type = "Text"]
+pub enum AdminRole {
+    Owner,
+    Editor,
+    Moderator,
+}
+
+impl AdminRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminRole::Owner => "owner",
+            AdminRole::Editor => "editor",
+            AdminRole::Moderator => "moderator",
+        }
+    }
+}
+
+impl std::str::FromStr for AdminRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "owner" => Ok(AdminRole::Owner),
+            "editor" => Ok(AdminRole::Editor),
+            "moderator" => Ok(AdminRole::Moderator),
+            other => Err(anyhow::anyhow!("unrecognized admin role: {}", other))
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for AdminRole {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<Text, Pg>::to_sql(self.as_str(), out)
+    }
+}
+
+impl FromSql<Text, Pg> for AdminRole {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+        <String as FromSql<Text, Pg>>::from_sql(bytes)?
+            .parse()
+            .map_err(|e: anyhow::Error| e.to_string().into())
+    }
+}
+
+pub type LiveFilter = diesel::dsl::And<
+    diesel::dsl::NotEq<posts::status, PostStatus>,
+    diesel::dsl::LtEq<posts::public_date, chrono::NaiveDateTime>
+>;
 
 impl Post {
     pub fn render_content(&self) -> String {
-        log::warn!("called");
-        let block_re = regex::Regex::new(BLOCK_MATH).unwrap();
-        let inline_re = regex::Regex::new(INLINE_MATH).unwrap();
-        let content =
-            block_re.replace_all(self.content.as_str(), |caps: &Captures| {
-                katex::render_with_opts(&caps[1], Opts::builder()
-                    .display_mode(true)
-                    .build()
-                    .unwrap()).unwrap_or_else(|e| format!("[MATH ERROR: {}]", e))
-            });
-        let content =
-            inline_re.replace_all(content.as_ref(), |caps: &Captures| {
-                katex::render_with_opts(&caps[1], Opts::builder()
-                    .display_mode(false)
-                    .build()
-                    .unwrap()).unwrap_or_else(|e| format!("[MATH ERROR: {}]", e))
-            });
-        let cmark = pulldown_cmark::Parser::new(content.as_ref());
-        let mut buffer = String::with_capacity(1024);
-        pulldown_cmark::html::push_html(&mut buffer, cmark);
-        buffer
+        crate::markdown::render(self.content.as_str())
     }
 
     pub fn tags_to_keywords(&self) -> String {
         self.tags.join(", ")
     }
 
+    /// Plain text abstract for the listings, which typeset the math in the browser.
     pub fn get_abstract(&self, limit: &usize) -> String {
-        use pulldown_cmark::*;
-        let mut abstract_content = String::new();
-        let parser = pulldown_cmark::Parser::new(self.content.as_str());
-        let mut indent = 0;
-        let mut status = 0;
-        'outer: for i in parser {
-            match i {
-                Event::Text(text) => {
-                    for i in text.chars() {
-                        if abstract_content.len() >= *limit && status == 0 {
-                            break 'outer;
-                        }
-                        abstract_content.push(i);
-                        status = match (status, i) {
-                            (0, '$') => 1,
-                            (1, '$') => 2,
-                            (1,  _ ) => 5,
-                            (2, '$') => 3,
-                            (3, '$') => 0,
-                            (5, '$') => 0,
-                            (_,  _ )   => status,
-                        }
-                    }
-                }
-                Event::Start(Tag::Item) => {
-                    indent += 1;
-                    abstract_content.extend(" ".repeat(indent).chars());
-                    abstract_content.push_str("- ");
-                }
-                Event::End(Tag::Item) => {
-                    indent -= 1;
-                    abstract_content.push('\n');
-                }
-                Event::HardBreak | Event::SoftBreak  => abstract_content.push('\n'),
-                Event::End(Tag::Heading(..))  | Event::End(Tag::Paragraph) => abstract_content.push('\n'),
-                _ => continue
-            }
-        }
-        abstract_content.extend("...".chars());
-        abstract_content
+        crate::markdown::abstract_text(self.content.as_str(), *limit)
+    }
+
+    /// Html abstract with the math rendered, for feeds and previews.
+    pub fn render_abstract(&self, limit: usize) -> String {
+        crate::markdown::render_abstract(self.content.as_str(), limit)
+    }
+
+    /// Html of the post with the headings to list above it, which are `None` when it has opted out
+    /// or is too short to need them.
+    pub fn render_with_contents(&self) -> (String, Option<Vec<Heading>>) {
+        let (content, headings) = crate::markdown::render_with_contents(self.content.as_str());
+        let table_of_contents = Some(headings)
+            .filter(|x| self.toc && x.len() > TOC_MIN_HEADINGS);
+        (content, table_of_contents)
     }
 }
 
@@ -102,6 +160,9 @@ pub struct NewPost<'a> {
     pub update_date: Option<&'a chrono::NaiveDateTime>,
     pub tags: Option<&'a [String]>,
     pub content: Option<&'a str>,
+    pub status: Option<&'a PostStatus>,
+    pub slug: Option<&'a str>,
+    pub toc: Option<bool>,
 }
 
 
@@ -113,6 +174,9 @@ pub struct NewPostRaw {
     pub update_date: Option<chrono::NaiveDateTime>,
     pub tags: Option<Vec<String>>,
     pub content: Option<String>,
+    pub status: Option<PostStatus>,
+    pub slug: Option<String>,
+    pub toc: Option<bool>,
 }
 
 #[derive(Insertable, Debug, Clone, diesel::AsChangeset)]
@@ -121,7 +185,8 @@ pub struct NewPage<'a> {
     pub title: Option<&'a str>,
     pub content: Option<&'a str>,
     pub important: Option<bool>,
-    pub description: Option<&'a str>
+    pub description: Option<&'a str>,
+    pub slug: Option<&'a str>,
 }
 
 #[derive(Insertable, Debug, Clone, diesel::AsChangeset)]
@@ -130,20 +195,24 @@ pub struct NewPageRaw {
     pub title: Option<String>,
     pub content: Option<String>,
     pub important: Option<bool>,
-    pub description: Option<String>
+    pub description: Option<String>,
+    pub slug: Option<String>,
 }
 
-#[derive(diesel::Queryable, diesel::Identifiable, serde::Serialize, Debug, serde::Deserialize)]
+#[derive(diesel::Queryable, diesel::Identifiable, Insertable, serde::Serialize, Debug, serde::Deserialize)]
+#[table_name = "pages"]
 pub struct Page {
     pub id: i32,
     pub title: String,
     pub content: String,
     pub important: bool,
-    pub description: String
+    pub description: String,
+    pub slug: String,
 }
 
-#[derive(diesel::Queryable, diesel::Identifiable, diesel::Associations, serde::Serialize, Debug, serde::Deserialize)]
+#[derive(diesel::Queryable, diesel::Identifiable, diesel::Associations, Insertable, serde::Serialize, Debug, serde::Deserialize)]
 #[belongs_to(Post)]
+#[table_name = "comments"]
 pub struct Comment {
     pub id: i32,
     pub post_id: i32,
@@ -153,6 +222,10 @@ pub struct Comment {
     pub signature: String,
     pub finger_print: String,
     pub sha3_512: Vec<u8>,
+    pub parent_id: Option<i32>,
+    pub created_at: chrono::NaiveDateTime,
+    pub approved: bool,
+    pub signed: bool,
 }
 
 #[derive(Insertable)]
@@ -165,28 +238,190 @@ pub struct NewComment {
     pub signature:String,
     pub finger_print:String,
     pub sha3_512: Vec<u8>,
+    pub parent_id: Option<i32>,
+    pub created_at: chrono::NaiveDateTime,
+    pub approved: bool,
+    pub signed: bool,
+}
+
+#[derive(diesel::Queryable, serde::Serialize, Debug, serde::Deserialize)]
+pub struct CommenterKey {
+    pub finger_print: String,
+    pub key_ids: Vec<String>,
+    pub user_id: String,
+    pub armored_key: String,
+    pub created_at: chrono::NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "commenter_keys"]
+pub struct NewCommenterKey {
+    pub finger_print: String,
+    pub key_ids: Vec<String>,
+    pub user_id: String,
+    pub armored_key: String,
+}
+
+#[derive(diesel::Queryable, serde::Serialize, Debug, serde::Deserialize)]
+pub struct AdminKey {
+    pub id: i32,
+    pub name: String,
+    pub role: AdminRole,
+    pub finger_print: String,
+    pub public_key: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "admin_keys"]
+pub struct NewAdminKey {
+    pub name: String,
+    pub role: AdminRole,
+    pub finger_print: String,
+    pub public_key: String,
+}
+
+#[derive(diesel::Queryable, serde::Serialize, Debug, serde::Deserialize)]
+pub struct AuditLog {
+    pub id: i32,
+    pub created_at: chrono::NaiveDateTime,
+    pub finger_print: String,
+    pub operation: String,
+    pub model_type: Option<String>,
+    pub model_id: Option<i32>,
+    pub success: bool,
+    pub message: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_logs"]
+pub struct NewAuditLog {
+    pub created_at: chrono::NaiveDateTime,
+    pub finger_print: String,
+    pub operation: String,
+    pub model_type: Option<String>,
+    pub model_id: Option<i32>,
+    pub success: bool,
+    pub message: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+/// A comment together with its nesting level in the reply thread.
+pub struct ThreadedComment {
+    pub depth: usize,
+    pub comment: Comment,
+}
+
+impl ThreadedComment {
+    const MAX_INDENT: usize = 5;
+
+    pub fn indent(&self) -> usize {
+        self.depth.min(Self::MAX_INDENT) * 4
+    }
+}
+
+#[derive(diesel::Queryable, diesel::Identifiable, serde::Serialize, Debug, serde::Deserialize)]
+pub struct Revision {
+    pub id: i32,
+    pub model_type: String,
+    pub model_id: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub finger_print: String,
+}
+
+/// Revisions of posts and pages alike are stamped with the time they are written, as pages keep
+/// no update date.
+#[derive(Insertable)]
+#[table_name = "revisions"]
+pub struct NewRevision {
+    pub model_type: String,
+    pub model_id: i32,
+    pub title: String,
+    pub content: String,
+    pub tags: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub finger_print: String,
+}
+
+impl NewRevision {
+    pub fn of_post(post: &Post, finger_print: &str) -> Self {
+        NewRevision {
+            model_type: "post".to_string(),
+            model_id: post.id,
+            title: post.title.clone(),
+            content: post.content.clone(),
+            tags: post.tags.clone(),
+            created_at: chrono::Utc::now().naive_local(),
+            finger_print: finger_print.to_string(),
+        }
+    }
+
+    pub fn of_page(page: &Page, finger_print: &str) -> Self {
+        NewRevision {
+            model_type: "page".to_string(),
+            model_id: page.id,
+            title: page.title.clone(),
+            content: page.content.clone(),
+            tags: Vec::new(),
+            created_at: chrono::Utc::now().naive_local(),
+            finger_print: finger_print.to_string(),
+        }
+    }
+}
+
+impl Revision {
+    /// Plain text form of the revision used for diffing, with the metadata on top.
+    pub fn to_document(&self) -> String {
+        format!("title: {}\ntags: {}\n\n{}", self.title, self.tags.join(", "), self.content)
+    }
 }
 
 impl Comment {
-    pub fn render_safe_content(&self) -> String {
-        let parser = pulldown_cmark::Parser::new(self.content.as_str());
-        let mut buffer = String::with_capacity(1024);
-        pulldown_cmark::html::push_html(&mut buffer, parser);
-        ammonia::clean(buffer.as_str())
+    /// Arrange comments (already sorted by creation time) into depth-first thread order.
+    /// Replies whose parent is missing are shown at the top level.
+    pub fn into_thread(comments: Vec<Comment>) -> Vec<ThreadedComment> {
+        let ids: hashbrown::HashSet<i32> = comments.iter().map(|x| x.id).collect();
+        let mut children: hashbrown::HashMap<Option<i32>, Vec<Comment>> = hashbrown::HashMap::new();
+        for comment in comments {
+            let parent = comment.parent_id.filter(|x| ids.contains(x));
+            children.entry(parent).or_insert_with(Vec::new).push(comment);
+        }
+        let mut thread = Vec::new();
+        let mut stack: Vec<ThreadedComment> = children.remove(&None)
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .map(|comment| ThreadedComment { depth: 0, comment })
+            .collect();
+        while let Some(current) = stack.pop() {
+            if let Some(replies) = children.remove(&Some(current.comment.id)) {
+                let depth = current.depth + 1;
+                stack.extend(replies.into_iter()
+                    .rev()
+                    .map(|comment| ThreadedComment { depth, comment }));
+            }
+            thread.push(current);
+        }
+        thread
+    }
+
+    pub fn render_safe_content(&self, extensions: Extensions) -> String {
+        crate::markdown::render_safe(self.content.as_str(), extensions)
     }
 }
 
 impl Page {
-    pub fn render_content(&self) -> String {
-        let cmark = pulldown_cmark::Parser::new(self.content.as_str());
-        let mut buffer = String::with_capacity(1024);
-        pulldown_cmark::html::push_html(&mut buffer, cmark);
-        buffer
+    pub fn encoded_slug(&self) -> String {
+        crate::utils::encode_url(&self.slug)
     }
-    pub fn translate_title(&self) -> String {
-        self.title
-            .replace(" ", "-")
-            .to_ascii_lowercase()
+
+    pub fn render_content(&self) -> String {
+        crate::markdown::render(self.content.as_str())
     }
 }
 
@@ -196,7 +431,10 @@ pub type PostColumns = (
     crate::schema::posts::public_date,
     crate::schema::posts::update_date,
     crate::schema::posts::tags,
-    crate::schema::posts::content
+    crate::schema::posts::content,
+    crate::schema::posts::status,
+    crate::schema::posts::slug,
+    crate::schema::posts::toc
 );
 
 
@@ -206,18 +444,27 @@ pub const POST_COLUMNS: PostColumns = (
     crate::schema::posts::public_date,
     crate::schema::posts::update_date,
     crate::schema::posts::tags,
-    crate::schema::posts::content
+    crate::schema::posts::content,
+    crate::schema::posts::status,
+    crate::schema::posts::slug,
+    crate::schema::posts::toc
 );
 
 impl Post {
+    /// Search the posts; `live_only` hides drafts and scheduled posts, which only the admin API
+    /// should be able to find.
     #[inline(always)]
-    pub fn list(connection: &Conn, search: &str, page_number: Option<i64>) -> tide::Result<Vec<Self>> {
+    pub fn list(connection: &diesel::PgConnection, search: &str, page_number: Option<i64>, live_only: bool) -> tide::Result<Vec<Self>> {
         use crate::schema::posts::dsl::*;
         use crate::schema;
         use diesel_full_text_search::{plainto_tsquery, TsVectorExtensions};
 
         let mut query = schema::posts::table.into_boxed::<Pg>();
 
+        if live_only {
+            query = query.filter(Post::is_live());
+        }
+
         if !search.is_empty() {
             query = query
                 .filter(text_searchable.matches(plainto_tsquery(search)));
@@ -239,18 +486,24 @@ impl Post {
         }
     }
 
-    pub fn translate_title(&self) -> String {
-        self.title
-            .replace(" ", "-")
-            .to_ascii_lowercase()
+    /// Filter selecting the posts that readers are allowed to see right now.
+    pub fn is_live() -> LiveFilter {
+        use crate::schema::posts::dsl::*;
+        use diesel::{BoolExpressionMethods, ExpressionMethods};
+        status.ne(PostStatus::Draft)
+            .and(public_date.le(chrono::Utc::now().naive_local()))
     }
 
     pub fn translate_tags(&self) -> Vec<(&str, String)> {
         self.tags.iter()
-            .map(|x| (x.as_str(), x.to_ascii_lowercase()
-                .replace(" ", "-")))
+            .map(|x| (x.as_str(), crate::utils::encode_url(x.to_lowercase()
+                .replace(" ", "-"))))
             .collect()
     }
+
+    pub fn encoded_slug(&self) -> String {
+        crate::utils::encode_url(&self.slug)
+    }
 }
 
 diesel::joinable!(comments -> posts (post_id));
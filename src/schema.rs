@@ -1,3 +1,53 @@
+diesel::table! {
+    admin_keys (id) {
+        id -> Int4,
+        name -> Varchar,
+        role -> Varchar,
+        finger_print -> Varchar,
+        public_key -> Text,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    api_nonces (time_stamp, nonce) {
+        time_stamp -> Int8,
+        nonce -> Varchar,
+    }
+}
+
+diesel::table! {
+    approved_finger_prints (finger_print) {
+        finger_print -> Varchar,
+        approved_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_logs (id) {
+        id -> Int4,
+        created_at -> Timestamp,
+        finger_print -> Varchar,
+        operation -> Varchar,
+        model_type -> Nullable<Varchar>,
+        model_id -> Nullable<Int4>,
+        success -> Bool,
+        message -> Nullable<Text>,
+        client_ip -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    commenter_keys (finger_print) {
+        finger_print -> Varchar,
+        key_ids -> Array<Text>,
+        user_id -> Varchar,
+        armored_key -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     comments (id) {
         id -> Int4,
@@ -8,6 +58,10 @@ diesel::table! {
         signature -> Text,
         finger_print -> Varchar,
         sha3_512 -> Bytea,
+        parent_id -> Nullable<Int4>,
+        created_at -> Timestamp,
+        approved -> Bool,
+        signed -> Bool,
     }
 }
 
@@ -18,6 +72,7 @@ diesel::table! {
         content -> Text,
         important -> Bool,
         description -> Text,
+        slug -> Varchar,
     }
 }
 
@@ -30,19 +85,57 @@ diesel::table! {
         tags -> Array<Text>,
         content -> Text,
         text_searchable -> diesel_full_text_search::TsVector,
+        status -> Varchar,
+        slug -> Varchar,
+        toc -> Bool,
+    }
+}
+
+diesel::table! {
+    rendered_html (model_type, model_id) {
+        model_type -> Varchar,
+        model_id -> Int4,
+        content -> Text,
+        abstract_content -> Nullable<Text>,
+        rendered_at -> Timestamp,
+        renderer_version -> Int4,
+        table_of_contents -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    revisions (id) {
+        id -> Int4,
+        model_type -> Varchar,
+        model_id -> Int4,
+        title -> Varchar,
+        content -> Text,
+        tags -> Array<Text>,
+        created_at -> Timestamp,
+        finger_print -> Varchar,
+    }
+}
+
+diesel::table! {
+    slug_aliases (id) {
+        id -> Int4,
+        model_type -> Varchar,
+        model_id -> Int4,
+        slug -> Varchar,
+        created_at -> Timestamp,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
+    admin_keys,
+    api_nonces,
+    approved_finger_prints,
+    audit_logs,
+    commenter_keys,
     comments,
     pages,
     posts,
+    rendered_html,
+    revisions,
+    slug_aliases,
 );
-
-diesel::sql_function!(fn lower(x: diesel::types::Varchar) -> diesel::types::Varchar);
-diesel::sql_function!(fn translate(x: diesel::types::Varchar, y: diesel::types::Varchar, z: diesel::types::Varchar) -> diesel::types::Varchar);
-diesel::sql_function!(fn
-    concat(a: diesel::types::Varchar,
-           b: diesel::types::Varchar,
-           c: diesel::types::Varchar,
-           d: diesel::types::Varchar) -> diesel::types::Varchar);
\ No newline at end of file
@@ -8,19 +8,18 @@ use diesel::sql_query;
 use sitemap::structs::UrlEntry;
 use tide::{Redirect, Request, Response, Status, StatusCode};
 
-use crate::{ConnPool, ServerState};
-use crate::api::JsonRequest;
-use crate::crypto::Packet;
-use crate::model::{Comment, NewComment, Page, Post, POST_COLUMNS};
+use crate::{ConnPool, FEED_ABSTRACT, ServerState};
+use crate::api::{JsonRequest, JsonResponse};
+use crate::crypto::{Challenge, Packet, PublicKey};
+use crate::markdown::{Extensions, Heading, RENDERER_VERSION};
+use crate::model::{AdminRole, Comment, NewAuditLog, NewComment, Page, Post, POST_COLUMNS};
 use crate::template::{PostsTemplate, Tag, TagTemplate};
 
 static EMAIL_REGEX: &str = "^[A-Za-z0-9._%-]+@[A-Za-z0-9.-]+[.][A-Za-z]+$";
 use crate::PAGE_LIMIT;
-use crate::utils::RenderKaTeX;
+use crate::utils::encode_url;
 
 pub async fn serve_posts(request: Request<ServerState>) -> tide::Result<tide::Response> {
-    use crate::schema::posts::dsl::*;
-    let pool = &request.state().pool;
     let page_number: i64 = {
         let pat = request.url()
             .path()
@@ -28,27 +27,33 @@ pub async fn serve_posts(request: Request<ServerState>) -> tide::Result<tide::Re
             .trim_start_matches("/");
         if pat.is_empty() { 0 } else { pat.parse()? }
     };
+    let page = render_posts(&request.state().pool, request.state().blog_name.as_str(), page_number).await?;
+    let mut responce = tide::Response::new(StatusCode::Ok);
+    responce.set_body(page);
+    responce.set_content_type(http_types::mime::HTML);
+    Ok(
+        responce
+    )
+}
+
+pub async fn render_posts(pool: &ConnPool, blog_name: &str, page_number: i64) -> tide::Result<String> {
+    use crate::schema::posts::dsl::*;
     let all_posts = posts
         .select(POST_COLUMNS)
+        .filter(Post::is_live())
         .order_by(id.desc())
         .limit(PAGE_LIMIT)
         .offset(page_number * PAGE_LIMIT)
-        .load_async::<Post>(&pool)
+        .load_async::<Post>(pool)
         .await
         .status(StatusCode::InternalServerError)?;
 
     let posts_template = PostsTemplate {
-        blog_name: request.state().blog_name.as_str(),
+        blog_name,
         posts: all_posts,
         page_number,
     };
-    let page = posts_template.render()?;
-    let mut responce = tide::Response::new(StatusCode::Ok);
-    responce.set_body(page);
-    responce.set_content_type(http_types::mime::HTML);
-    Ok(
-        responce
-    )
+    Ok(posts_template.render()?)
 }
 
 #[inline(always)]
@@ -75,7 +80,7 @@ pub async fn error_handle(mut response: tide::Response) -> tide::Result<tide::Re
 
 pub async fn serve_post(request: Request<ServerState>) -> tide::Result<Response> {
     use crate::schema::posts::dsl as p;
-    use crate::schema::lower;
+    use crate::schema::slug_aliases::dsl as a;
     let path = request.url().path().trim_start_matches("/");
     if path.contains('/') || !path.ends_with(".html") {
         return Ok(Response::new(StatusCode::NotFound));
@@ -83,29 +88,97 @@ pub async fn serve_post(request: Request<ServerState>) -> tide::Result<Response>
     let conn = &request.state().pool;
     let name = path.trim_end_matches(".html");
     let name = percent_encoding::percent_decode_str(name).decode_utf8()?
-        .replace("-", " ");
-    let post: Post = p::posts
+        .to_lowercase();
+    let post = p::posts
         .select(POST_COLUMNS)
-        .filter(lower(p::title).eq(name.to_ascii_lowercase()))
+        .filter(p::slug.eq(name.clone()))
+        .filter(Post::is_live())
         .first_async::<Post>(conn)
-        .await
-        .status(StatusCode::NotFound)?;
-    render_post(post, request.state().blog_name.as_str(), conn).await
+        .await;
+    match post {
+        Ok(post) => render_post(post, request.state()).await,
+        Err(_) => {
+            let current: String = a::slug_aliases
+                .inner_join(p::posts.on(p::id.eq(a::model_id)))
+                .select(p::slug)
+                .filter(a::model_type.eq("post"))
+                .filter(a::slug.eq(name))
+                .filter(Post::is_live())
+                .first_async(conn)
+                .await
+                .status(StatusCode::NotFound)?;
+            Ok(Redirect::permanent(format!("/post/{}.html", encode_url(current))).into())
+        }
+    }
+}
+
+pub async fn render_post(post: Post, state: &ServerState) -> tide::Result<Response> {
+    let challenge = Challenge::issue(state.challenge_secret.as_slice(), post.id, state.pow_difficulty)
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+    let page = render_post_page(&state.pool, state.blog_name.as_str(), post, Some(challenge),
+                                state.comment_extensions).await?;
+    Ok(normal_page(page))
+}
+
+/// Html and table of contents stored by the api when the post or page was written; `None` for
+/// models written before the cache existed or by an older renderer, until `rebuild-cache` is run.
+async fn cached_content(pool: &ConnPool, model_type: &'static str, model_id: i32)
+                        -> tide::Result<Option<(String, Option<Vec<Heading>>)>> {
+    use crate::schema::rendered_html::dsl as h;
+    let cached = h::rendered_html
+        .select((h::content, h::table_of_contents))
+        .filter(h::model_type.eq(model_type))
+        .filter(h::model_id.eq(model_id))
+        .filter(h::renderer_version.eq(RENDERER_VERSION))
+        .load_async::<(String, Option<serde_json::Value>)>(pool)
+        .await?
+        .pop();
+    match cached {
+        Some((content, table_of_contents)) =>
+            Ok(Some((content, table_of_contents.map(serde_json::from_value).transpose()?))),
+        None => Ok(None)
+    }
 }
 
-pub async fn render_post(post: Post, blog_name: &str, conn: &ConnPool) -> tide::Result<Response> {
+/// Feed abstracts stored by the api, by post id.
+async fn cached_abstracts(pool: &ConnPool) -> tide::Result<hashbrown::HashMap<i32, String>> {
+    use crate::schema::rendered_html::dsl as h;
+    Ok(h::rendered_html
+        .select((h::model_id, h::abstract_content))
+        .filter(h::model_type.eq("post"))
+        .filter(h::renderer_version.eq(RENDERER_VERSION))
+        .load_async::<(i32, Option<String>)>(pool)
+        .await?
+        .into_iter()
+        .filter_map(|(model_id, content)| content.map(|x| (model_id, x)))
+        .collect())
+}
+
+/// Render a post with its approved comments; without a challenge the comment form is left out.
+pub async fn render_post_page(pool: &ConnPool, blog_name: &str, post: Post, challenge: Option<Challenge>,
+                              comment_extensions: Extensions) -> tide::Result<String> {
     use crate::schema::comments::dsl as c;
-    let pid = post.id;
+    let (content, table_of_contents) = match cached_content(pool, "post", post.id).await? {
+        Some(cached) => cached,
+        None => post.render_with_contents(),
+    };
     let all_comments = c::comments
-        .filter(c::post_id.eq(pid))
-        .load_async::<Comment>(&conn).await?;
+        .filter(c::post_id.eq(post.id))
+        .filter(c::approved)
+        .order_by((c::created_at, c::id))
+        .load_async::<Comment>(pool).await?;
     let template = crate::template::PostTemplate {
         post,
-        comments: all_comments,
+        content,
+        table_of_contents,
+        comments: Comment::into_thread(all_comments),
+        comment_extensions,
         blog_name,
+        read_only: challenge.is_none(),
+        challenge: challenge.as_ref().map(Challenge::token).unwrap_or_default(),
+        difficulty: challenge.as_ref().map(Challenge::difficulty).unwrap_or_default(),
     };
-    let page = template.render()?;
-    Ok(normal_page(page))
+    Ok(template.render()?)
 }
 
 pub async fn serve_lucky(request: Request<ServerState>) -> tide::Result<Response> {
@@ -116,9 +189,13 @@ pub async fn serve_lucky(request: Request<ServerState>) -> tide::Result<Response
                       public_date,
                       update_date,
                       tags,
-                      content
+                      content,
+                      status,
+                      slug,
+                      toc
                FROM posts TABLESAMPLE bernoulli(
                    133 / (SELECT reltuples FROM pg_class where relname = 'posts'))
+               WHERE status <> 'draft' AND public_date <= (now() at time zone 'utc')
                limit 5
               ) as result order by random() limit 1"#)
         .load_async(conn)
@@ -131,15 +208,16 @@ pub async fn serve_lucky(request: Request<ServerState>) -> tide::Result<Response
     let post = match post {
         Ok(p) => p,
         _ => p::posts.select(POST_COLUMNS)
+            .filter(Post::is_live())
             .first_async(conn)
             .await?
     };
-    render_post(post, request.state().blog_name.as_str(), conn).await
+    render_post(post, request.state()).await
 }
 
 pub async fn serve_page(request: Request<ServerState>) -> tide::Result<Response> {
     use crate::schema::pages::dsl as p;
-    use crate::schema::lower;
+    use crate::schema::slug_aliases::dsl as a;
     let path = request.url().path().trim_start_matches("/");
     if path.contains('/') || !path.ends_with(".html") {
         return Ok(Response::new(StatusCode::NotFound));
@@ -147,28 +225,56 @@ pub async fn serve_page(request: Request<ServerState>) -> tide::Result<Response>
     let conn = &request.state().pool;
     let name = path.trim_end_matches(".html");
     let name = percent_encoding::percent_decode_str(name).decode_utf8()?
-        .replace("-", " ");
-    let page = p::pages
-        .filter(lower(p::title).eq(name.to_ascii_lowercase()))
+        .to_lowercase();
+    let page = match p::pages
+        .filter(p::slug.eq(name.clone()))
         .first_async::<Page>(&conn)
-        .await
-        .status(StatusCode::NotFound)?;
-    let template = crate::template::PageTemplate {
-        blog_name: request.state().blog_name.as_str(),
-        page: &page,
+        .await {
+        Ok(page) => page,
+        Err(_) => {
+            let current: String = a::slug_aliases
+                .inner_join(p::pages.on(p::id.eq(a::model_id)))
+                .select(p::slug)
+                .filter(a::model_type.eq("page"))
+                .filter(a::slug.eq(name))
+                .first_async(&conn)
+                .await
+                .status(StatusCode::NotFound)?;
+            return Ok(Redirect::permanent(format!("/page/{}.html", encode_url(current))).into());
+        }
     };
-    let page = template.render()?;
+    let page = render_page(conn, request.state().blog_name.as_str(), &page).await?;
     Ok(normal_page(page))
 }
 
+pub async fn render_page(pool: &ConnPool, blog_name: &str, page: &Page) -> tide::Result<String> {
+    let content = cached_content(pool, "page", page.id).await?
+        .map(|(content, _)| content)
+        .unwrap_or_else(|| page.render_content());
+    let template = crate::template::PageTemplate {
+        blog_name,
+        page,
+        content,
+    };
+    Ok(template.render()?)
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 pub struct CommentForm {
     post_id: i32,
     comment_nickname: String,
     comment_email: String,
     comment_content: String,
+    #[serde(default)]
+    parent_id: String,
+    #[serde(default)]
+    pow_challenge: String,
+    #[serde(default)]
+    pow_counter: String,
 }
 
+const CLEARSIGN_HEADER: &str = "-----BEGIN PGP SIGNED MESSAGE-----";
+
 
 pub async fn handle_comment(mut request: Request<ServerState>) -> tide::Result<Response> {
     use crate::schema::posts::dsl as p;
@@ -180,16 +286,69 @@ pub async fn handle_comment(mut request: Request<ServerState>) -> tide::Result<R
         !regex::Regex::new(EMAIL_REGEX)?.is_match(form.comment_email.as_str()) {
         return Err(tide::Error::from_str(StatusCode::BadRequest, "invalid field"));
     }
-    match diesel::dsl::select(diesel::dsl::exists(p::posts.filter(p::id.eq(form.post_id))))
+    match diesel::dsl::select(diesel::dsl::exists(p::posts.filter(p::id.eq(form.post_id))
+        .filter(Post::is_live())))
         .get_result_async(&conn).await {
         Ok(true) => (),
         _ => {
             return Err(tide::Error::from_str(StatusCode::BadRequest, "invalid post id"));
         }
     }
-    let (real_content, finger_print) = crate::utils::gpg_decrypt(form.comment_content.as_str())
-        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?;
+    let parent_id = if form.parent_id.trim().is_empty() {
+        None
+    } else {
+        let parent: i32 = form.parent_id.trim().parse()
+            .status(StatusCode::BadRequest)?;
+        match diesel::dsl::select(diesel::dsl::exists(c::comments.filter(c::id.eq(parent))
+            .filter(c::post_id.eq(form.post_id))
+            .filter(c::approved)))
+            .get_result_async(&conn).await {
+            Ok(true) => Some(parent),
+            _ => {
+                return Err(tide::Error::from_str(StatusCode::BadRequest, "invalid parent comment"));
+            }
+        }
+    };
+    let signed = form.comment_content.trim_start().starts_with(CLEARSIGN_HEADER);
+    let (real_content, finger_print) = if signed {
+        crate::keyring::verify_cleartext(conn, form.comment_content.as_str())
+            .await
+            .map_err(|e| tide::Error::from_str(e.status(), e))?
+    } else {
+        let state = request.state();
+        let mut keeper = state.challenge_keeper.clone();
+        Challenge::parse(form.pow_challenge.as_str())
+            .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, e))?
+            .verify(state.challenge_secret.as_slice(),
+                    form.post_id,
+                    state.pow_difficulty,
+                    form.pow_counter.as_str(),
+                    &mut keeper)
+            .await
+            .map_err(|e| tide::Error::from_str(StatusCode::Forbidden, e))?;
+        if form.comment_content.trim().is_empty() {
+            return Err(tide::Error::from_str(StatusCode::BadRequest, "empty comment"));
+        }
+        (form.comment_content.clone(), String::new())
+    };
+    if signed {
+        if let Err(wait) = request.state().finger_print_limiter.take(finger_print.as_str()) {
+            return Ok(crate::limit::too_many_requests(wait));
+        }
+    }
 
+    let approved = if !request.state().moderation {
+        true
+    } else if signed && request.state().trust_approved {
+        use crate::schema::approved_finger_prints::dsl as f;
+        diesel::dsl::select(diesel::dsl::exists(f::approved_finger_prints
+            .filter(f::finger_print.eq(finger_print.clone()))))
+            .get_result_async(&conn)
+            .await
+            .unwrap_or(false)
+    } else {
+        false
+    };
     let hash = easy_hasher::easy_hasher::sha3_512(&form.comment_content)
         .to_vec();
     let comment = NewComment {
@@ -200,21 +359,23 @@ pub async fn handle_comment(mut request: Request<ServerState>) -> tide::Result<R
         signature: form.comment_content,
         finger_print,
         sha3_512: hash,
+        parent_id,
+        created_at: chrono::Utc::now().naive_local(),
+        approved,
+        signed,
     };
     diesel::insert_into(c::comments)
         .values(comment)
         .execute_async(&conn)
         .await?;
-    let title: String = p::posts.select(p::title).filter(p::id.eq(form.post_id))
+    let slug: String = p::posts.select(p::slug).filter(p::id.eq(form.post_id))
         .first_async(&conn)
         .await
         .status(StatusCode::NotFound)?;
-    Ok(Redirect::new(format!("/post/{}.html", title.replace(" ", "-")
-        .to_ascii_lowercase())).into())
+    Ok(Redirect::new(format!("/post/{}.html", encode_url(slug))).into())
 }
 
 pub async fn serve_tag(request: Request<ServerState>) -> tide::Result<tide::Response> {
-    use crate::schema::posts::dsl::*;
     let url = request.url().path().trim_start_matches("/");
     let url_split = url.split("/").collect::<Vec<&str>>();
     if url_split.len() != 1 && url_split.len() != 2 {
@@ -223,31 +384,39 @@ pub async fn serve_tag(request: Request<ServerState>) -> tide::Result<tide::Resp
     let page_number = if url_split.len() == 2 {
         url_split[1].parse()?
     } else { 0 };
-    let conn = &request.state().pool;
     let old_tag = percent_encoding::percent_decode_str(url_split[0])
         .decode_utf8()?
-        .to_ascii_lowercase();
+        .to_lowercase();
     let real_tag = old_tag.replace("-", " ");
+    let page = render_tag(&request.state().pool, request.state().blog_name.as_str(),
+                          real_tag.as_str(), page_number).await?;
+    Ok(
+        normal_page(page)
+    )
+}
+
+pub async fn render_tag(pool: &ConnPool, blog_name: &str, real_tag: &str, page_number: i64) -> tide::Result<String> {
+    use crate::schema::posts::dsl::*;
+    let translated_name = encode_url(real_tag.replace(" ", "-"));
     let all_posts = posts
         .select(POST_COLUMNS)
         .order_by(id)
-        .filter(tags.contains(vec![real_tag.as_str()]))
+        .filter(tags.contains(vec![real_tag.to_string()]))
+        .filter(Post::is_live())
         .limit(PAGE_LIMIT)
         .offset(page_number * PAGE_LIMIT)
-        .load::<Post>(&conn.get()?)
+        .load_async::<Post>(pool)
+        .await
         .status(StatusCode::InternalServerError)?;
 
     let tag_template = TagTemplate {
-        blog_name: request.state().blog_name.as_str(),
-        name: real_tag.as_ref(),
+        blog_name,
+        name: real_tag,
         posts: all_posts,
         page_number,
-        translated_name: old_tag.as_ref(),
+        translated_name: translated_name.as_str(),
     };
-    let page = tag_template.render()?;
-    Ok(
-        normal_page(page)
-    )
+    Ok(tag_template.render()?)
 }
 
 pub async fn serve_comment_raw(request: Request<ServerState>) -> tide::Result<String> {
@@ -257,6 +426,7 @@ pub async fn serve_comment_raw(request: Request<ServerState>) -> tide::Result<St
     let conn = &request.state().pool;
     let target: String = comments.select(signature)
         .filter(id.eq(cid))
+        .filter(approved)
         .first_async(&conn)
         .await
         .status(StatusCode::NotFound)?;
@@ -270,6 +440,7 @@ pub async fn serve_post_raw(request: Request<ServerState>) -> tide::Result<Strin
     let conn = &request.state().pool;
     let target: String = posts.select(content)
         .filter(id.eq(cid))
+        .filter(Post::is_live())
         .first_async(&conn)
         .await
         .status(StatusCode::NotFound)?;
@@ -291,11 +462,26 @@ pub async fn serve_page_raw(request: Request<ServerState>) -> tide::Result<Strin
     Ok(target)
 }
 
+pub async fn serve_highlight_css(request: Request<ServerState>) -> tide::Result<Response> {
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_body(request.state().highlight_css.as_str());
+    response.set_content_type(http_types::mime::CSS);
+    Ok(response)
+}
+
 pub async fn serve_tags(request: Request<ServerState>) -> tide::Result<Response> {
+    let tag_vector = tag_counts(&request.state().pool).await?;
+    Ok(
+        normal_page(render_tags(request.state().blog_name.as_str(), tag_vector)?)
+    )
+}
+
+/// Live posts per tag, the most used tags first.
+pub async fn tag_counts(pool: &ConnPool) -> tide::Result<Vec<Tag>> {
     use crate::schema::posts::dsl::*;
-    let conn = &request.state().pool;
     let all_tags: Vec<String> = posts.select(tags)
-        .load_async::<Vec<String>>(&conn)
+        .filter(Post::is_live())
+        .load_async::<Vec<String>>(pool)
         .await
         .status(StatusCode::InternalServerError)?
         .into_iter()
@@ -303,7 +489,7 @@ pub async fn serve_tags(request: Request<ServerState>) -> tide::Result<Response>
         .collect();
     let map = all_tags.into_iter()
         .fold(hashbrown::HashMap::new(), |mut acc, next| {
-            *acc.entry(next.to_ascii_lowercase()).or_insert(0) += 1;
+            *acc.entry(next.to_lowercase()).or_insert(0) += 1;
             acc
         });
     let mut tag_vector = Vec::new();
@@ -311,14 +497,16 @@ pub async fn serve_tags(request: Request<ServerState>) -> tide::Result<Response>
         tag_vector.push(Tag { tag, count });
     }
     tag_vector.sort_by(|x, y| y.cmp(x));
+    Ok(tag_vector)
+}
+
+pub fn render_tags(blog_name: &str, tag_vector: Vec<Tag>) -> tide::Result<String> {
     let template = crate::template::TagsTemplate {
         tags_json: simd_json::to_string(&tag_vector)?,
         tags: tag_vector,
-        blog_name: request.state().blog_name.as_str(),
+        blog_name,
     };
-    Ok(
-        normal_page(template.render()?)
-    )
+    Ok(template.render()?)
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -332,7 +520,8 @@ pub async fn handle_search(mut request: Request<ServerState>) -> tide::Result<Re
     let conn = &request.state().pool;
     let all_posts = crate::model::Post::list(&conn.get()?,
                                              form.search.as_str(),
-                                             Some(form.page_number))?;
+                                             Some(form.page_number),
+                                             true)?;
     let template = crate::template::PostsSearch {
         blog_name: request.state().blog_name.as_str(),
         posts: all_posts,
@@ -389,65 +578,144 @@ pub async fn handle_remove_comment(mut request: Request<ServerState>) -> tide::R
     use crate::schema::comments::dsl::*;
     use crate::schema::posts::dsl as p;
     let body: RemoveCommentForm = request.body_form().await?;
-    let (mut raw_json, fp) = crate::utils::gpg_decrypt(body.signed_content.as_str())
-        .map_err(|_| tide::Error::from_str(StatusCode::BadRequest, "verification error"))?;
+    let conn = &request.state().pool;
+    let (mut raw_json, fp) = crate::keyring::verify_cleartext(conn, body.signed_content.as_str())
+        .await
+        .map_err(|e| tide::Error::from_str(e.status(), e))?;
+    if let Err(wait) = request.state().finger_print_limiter.take(fp.as_str()) {
+        return Ok(crate::limit::too_many_requests(wait));
+    }
     let remove: RemoveComment = simd_json::from_str(raw_json.as_mut_str())?;
     let now = std::time::SystemTime::now();
     let duration = now.duration_since(remove.time_stamp)?;
     if duration.as_secs() > 60 * 5 {
         return Err(tide::Error::from_str(StatusCode::RequestTimeout, "request timeout"));
     }
-    let conn = &request.state().pool;
-    let (cfp, cpost_id): (String, i32) = comments
-        .select((finger_print, post_id))
+    let (cfp, cpost_id, csigned): (String, i32, bool) = comments
+        .select((finger_print, post_id, signed))
         .filter(id.eq(remove.id)).first_async(&conn).await?;
-    if cfp != fp {
+    if !csigned {
+        Err(tide::Error::from_str(StatusCode::Unauthorized, "unsigned comments can only be removed by the owner"))
+    } else if crate::keyring::normalize_finger_print(&cfp) != fp {
         Err(tide::Error::from_str(StatusCode::Unauthorized, "fingerprint does not match"))
     } else {
         diesel::delete(comments.filter(id.eq(remove.id))).execute_async(&conn).await?;
-        let ctitle: String = p::posts.select(p::title).filter(p::id.eq(cpost_id))
+        let cslug: String = p::posts.select(p::slug).filter(p::id.eq(cpost_id))
             .first_async(&conn).await?;
-        Ok(Redirect::new(format!("/post/{}.html", ctitle.replace(" ", "-")
-            .to_ascii_lowercase())).into())
+        Ok(Redirect::new(format!("/post/{}.html", encode_url(cslug))).into())
     }
 }
 
 pub async fn index(request: Request<ServerState>) -> tide::Result<Response> {
+    Ok(
+        normal_page(render_index(&request.state().pool, request.state().blog_name.as_str()).await?)
+    )
+}
+
+pub async fn render_index(pool: &ConnPool, blog_name: &str) -> tide::Result<String> {
     use crate::schema::pages::dsl::*;
-    let conn = &request.state().pool;
-    let all_pages = pages.load_async::<Page>(&conn).await?;
-    let important_pages = pages.select((title, id))
+    let all_pages = pages.load_async::<Page>(pool).await?;
+    let important_pages = pages.select((title, id, slug))
         .filter(important)
-        .load_async::<(String, i32)>(&conn)
+        .load_async::<(String, i32, String)>(pool)
         .await?
         .into_iter()
-        .map(|(x, y)| {
-            let z = x.to_ascii_lowercase().replace(" ", "-");
-            (x, y, z)
-        })
+        .map(|(x, y, z)| (x, y, encode_url(z)))
         .collect::<Vec<_>>();
     let index = crate::template::IndexTemplate {
-        blog_name: request.state().blog_name.as_str(),
+        blog_name,
         pages: &all_pages,
         important_pages: &important_pages,
     };
-    Ok(
-        normal_page(index.render()?)
-    )
+    Ok(index.render()?)
 }
 
 pub async fn handle_api(mut request: Request<ServerState>) -> tide::Result<Response> {
+    use crate::schema::admin_keys::dsl as k;
     let packet: Packet = crate::utils::simdjson_body(&mut request).await?;
-    let mut addr = request.state().stamp_keeper.clone();
-    let key_pair = &request.state().key_pair;
-    let json_request: JsonRequest = packet.to_json_request(key_pair,
-                                                           Some(&mut addr),
+    let state = request.state();
+    let key_pair = &state.key_pair;
+    let owner_finger_print = key_pair.owner_public.fingerprint()
+        .map_err(|_| tide::Error::from_str(StatusCode::InternalServerError, "failed to get key fingerprint"))?;
+    // the key given at start is always an owner, other keys come from the admin key table
+    let admin_key = match packet.key_id() {
+        Some(key_id) if key_id != owner_finger_print => {
+            let (public_key, role): (String, AdminRole) = k::admin_keys
+                .select((k::public_key, k::role))
+                .filter(k::finger_print.eq(key_id.to_string()))
+                .filter(k::revoked_at.is_null())
+                .first_async(&state.pool)
+                .await
+                .map_err(|_| tide::Error::from_str(StatusCode::Unauthorized, "unknown or revoked key"))?;
+            let public_key = PublicKey::load(public_key.as_str())
+                .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+            Some((public_key, role, key_id.to_string()))
+        }
+        _ => None
+    };
+    let (peer, role, finger_print) = match admin_key.as_ref() {
+        Some((public_key, role, key_id)) => (public_key, *role, key_id.clone()),
+        None => (&key_pair.owner_public, AdminRole::Owner, owner_finger_print),
+    };
+    let json_request: JsonRequest = packet.to_json_request(&key_pair.server_private,
+                                                           peer,
+                                                           Some(state.nonce_store.as_ref()),
     ).await.map_err(|_| tide::Error::from_str(StatusCode::BadRequest, "failed to decode request"))?;
+    // counted once the signature is checked, so that nobody can spend the budget of another key
+    if let Err(wait) = state.admin_key_limiter.take(finger_print.as_str()) {
+        return Ok(crate::limit::too_many_requests(wait));
+    }
     let mut response = Response::new(StatusCode::Ok);
-    let response_content = json_request.handle(&request.state().pool)
+    // a batch is audited item by item, so that every change can be traced to its model
+    let audited: Vec<(&'static str, Option<&'static str>, Option<i32>)> = match &json_request {
+        JsonRequest::Batch(requests) => requests.iter()
+            .map(|x| (x.operation(), x.target().0, x.target().1))
+            .collect(),
+        request => vec![(request.operation(), request.target().0, request.target().1)],
+    };
+    let server_algorithm = key_pair.server_private.algorithm()
+        .map_err(|e| tide::Error::from_str(StatusCode::InternalServerError, e))?;
+    let response_content = json_request.handle(&state.pool, finger_print.as_str(), role, server_algorithm)
         .await;
+    let created_at = chrono::Utc::now().naive_local();
+    let client_ip = crate::limit::client_ip(&request).map(|x| x.to_string());
+    let outcomes: Vec<(bool, Option<String>)> = match &response_content {
+        // only the executed items have a result; when one fails the others are rolled back
+        JsonResponse::BatchResult { committed, results } => results.iter()
+            .map(|x| match x.error() {
+                Some(e) => (false, Some(e.to_string())),
+                None if !*committed => (false, Some("rolled back".to_string())),
+                None => (true, None),
+            })
+            .collect(),
+        // a rejected batch ran none of its items
+        other => vec![(other.error().is_none(), other.error().map(ToString::to_string)); audited.len()],
+    };
+    let audits: Vec<NewAuditLog> = audited.into_iter()
+        .zip(outcomes)
+        .map(|((operation, model_type, model_id), (success, message))| NewAuditLog {
+            created_at,
+            finger_print: finger_print.clone(),
+            operation: operation.to_string(),
+            model_type: model_type.map(ToString::to_string),
+            model_id,
+            success,
+            message,
+            client_ip: client_ip.clone(),
+        })
+        .collect();
+    // an empty batch has nothing to record
+    if !audits.is_empty() {
+        if let Err(e) = diesel::insert_into(crate::schema::audit_logs::table)
+            .values(audits)
+            .execute_async(&state.pool)
+            .await {
+            log::error!("failed to write audit log: {}", e);
+        }
+    }
     let response_packet = Packet::from_json_request_tide(response_content,
-                                                         key_pair,
+                                                         &key_pair.server_private,
+                                                         peer,
     ).await?;
     let response_json = simd_json::to_string(&response_packet)?;
     response.set_content_type(http_types::mime::JSON);
@@ -456,57 +724,74 @@ pub async fn handle_api(mut request: Request<ServerState>) -> tide::Result<Respo
 }
 
 pub async fn handle_rss(request: Request<ServerState>) -> tide::Result<Response> {
-    let conn = &request.state().pool;
+    let state = request.state();
+    let mime = http_types::mime::Mime::from_str("application/rss+xml")?;
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_content_type(mime);
+    response.set_body(render_rss(&state.pool, state.blog_name.as_str(), state.domain.as_str()).await?);
+    Ok(
+        response
+    )
+}
+
+pub async fn render_rss(pool: &ConnPool, blog_name: &str, domain: &str) -> tide::Result<String> {
     use crate::schema::posts::dsl::*;
     let all_posts: Vec<Post> = posts
         .select(POST_COLUMNS)
-        .load_async::<Post>(conn)
+        .filter(Post::is_live())
+        .load_async::<Post>(pool)
         .await
         .status(StatusCode::InternalServerError)?;
+    let mut abstracts = cached_abstracts(pool).await?;
     let items: Vec<rss::Item> = all_posts.into_iter()
         .map(|x| rss::ItemBuilder::default()
             .title(Some(x.title.clone()))
-            .link(Some(format!("{}/post/{}.html", request.state().domain,
-                               x.title.to_ascii_lowercase().replace(" ", "-"))))
+            .link(Some(format!("{}/post/{}.html", domain, x.encoded_slug())))
             .pub_date(Some(x.public_date.to_string()))
             .description(Some({
-                x.get_abstract(&1024).render_katex().unwrap_or_else(|x| x.to_string())
+                abstracts.remove(&x.id).unwrap_or_else(|| x.render_abstract(FEED_ABSTRACT))
             }))
             .build())
         .filter_map(|x| x.ok())
         .collect();
     let channel: rss::Channel = rss::ChannelBuilder::default()
-        .title(request.state().blog_name.as_str())
-        .link(request.state().domain.as_str())
+        .title(blog_name)
+        .link(domain)
         .description("blog")
         .items(items)
         .build()
         .map_err(|_| tide::Error::from_str(StatusCode::Ok, "RSS build failed"))?;
-    let mime = http_types::mime::Mime::from_str("application/rss+xml")?;
+    Ok(channel.to_string())
+}
+
+
+pub async fn handle_atom(request: Request<ServerState>) -> tide::Result<Response> {
+    let state = request.state();
+    let mime = http_types::mime::Mime::from_str("application/atom+xml")?;
     let mut response = Response::new(StatusCode::Ok);
     response.set_content_type(mime);
-    response.set_body(channel.to_string());
+    response.set_body(render_atom(&state.pool, state.blog_name.as_str(), state.domain.as_str()).await?);
     Ok(
         response
     )
 }
 
-
-pub async fn handle_atom(request: Request<ServerState>) -> tide::Result<Response> {
-    let conn = &request.state().pool;
+pub async fn render_atom(pool: &ConnPool, blog_name: &str, domain: &str) -> tide::Result<String> {
     use crate::schema::posts::dsl::*;
     let all_posts: Vec<Post> = posts
         .select(POST_COLUMNS)
-        .load_async::<Post>(&conn)
+        .filter(Post::is_live())
+        .load_async::<Post>(pool)
         .await
         .status(StatusCode::InternalServerError)?;
+    let mut abstracts = cached_abstracts(pool).await?;
     let entries: Vec<atom_syndication::Entry> = all_posts.into_iter()
         .map(|x| atom_syndication::ContentBuilder::default()
             .value(Some({
-                x.get_abstract(&1024).render_katex().unwrap_or_else(|x| x.to_string())
+                abstracts.remove(&x.id).unwrap_or_else(|| x.render_abstract(FEED_ABSTRACT))
             }))
             .content_type(Some("text/html".to_string()))
-            .src(Some(format!("{}/raw/post/{}", request.state().domain, x.id)))
+            .src(Some(format!("{}/raw/post/{}", domain, x.id)))
             .build()
             .and_then(|the_content| atom_syndication::EntryBuilder::default()
                 .title(x.title.as_str())
@@ -520,8 +805,7 @@ pub async fn handle_atom(request: Request<ServerState>) -> tide::Result<Response
                 })
                 .links(vec![{
                     let mut link = atom_syndication::Link::default();
-                    link.set_href(format!("{}/post/{}.html", request.state().domain, x.title
-                        .to_ascii_lowercase().replace(" ", "-")));
+                    link.set_href(format!("{}/post/{}.html", domain, x.encoded_slug()));
                     link.set_title(x.title.clone());
                     link
                 }])
@@ -530,49 +814,59 @@ pub async fn handle_atom(request: Request<ServerState>) -> tide::Result<Response
         .filter_map(|x| x.ok())
         .collect();
     let channel: atom_syndication::Feed = atom_syndication::FeedBuilder::default()
-        .title(request.state().blog_name.as_str())
+        .title(blog_name)
         .links(vec![{
             let mut link = atom_syndication::Link::default();
-            link.set_href(request.state().domain.clone());
-            link.set_title(request.state().blog_name.clone());
+            link.set_href(domain.to_string());
+            link.set_title(blog_name.to_string());
             link
         }])
         .entries(entries)
-        .icon(Some(format!("{}/static/img/ico.png", request.state().domain)))
+        .icon(Some(format!("{}/static/img/ico.png", domain)))
         .build()
         .map_err(|_| tide::Error::from_str(StatusCode::Ok, "ATOM build failed"))?;
-    let mime = http_types::mime::Mime::from_str("application/atom+xml")?;
+    Ok(channel.to_string())
+}
+
+pub async fn handle_sitemap(request: Request<ServerState>) -> tide::Result<Response> {
+    let state = request.state();
+    let mime = http_types::mime::Mime::from_str("application/xml")?;
     let mut response = Response::new(StatusCode::Ok);
     response.set_content_type(mime);
-    response.set_body(channel.to_string());
+    response.set_body(render_sitemap(&state.pool, state.domain.as_str()).await?);
     Ok(
         response
     )
 }
 
-pub async fn handle_sitemap(request: Request<ServerState>) -> tide::Result<Response> {
-    use crate::schema::{translate, concat, lower};
-    let conn = &request.state().pool;
+pub async fn render_sitemap(pool: &ConnPool, domain: &str) -> tide::Result<Vec<u8>> {
     let posts: Vec<(String, NaiveDateTime)> = {
         use crate::schema::posts::dsl::*;
-        posts.select((concat(request.state().domain.clone(), "/post/", translate(lower(title), " ", "-"), ".html"), update_date))
-            .load_async(&conn)
+        posts.select((slug, update_date))
+            .filter(Post::is_live())
+            .load_async::<(String, NaiveDateTime)>(pool)
             .await
             .status(StatusCode::InternalServerError)?
+            .into_iter()
+            .map(|(x, y)| (format!("{}/post/{}.html", domain, encode_url(x)), y))
+            .collect()
     };
     let pages: Vec<(String, bool)> = {
         use crate::schema::pages::dsl::*;
-        pages.select((concat(request.state().domain.clone(), "/page/", translate(lower(title), " ", "-"), ".html"), important))
-            .load_async(&conn)
+        pages.select((slug, important))
+            .load_async::<(String, bool)>(pool)
             .await
             .status(StatusCode::InternalServerError)?
+            .into_iter()
+            .map(|(x, y)| (format!("{}/page/{}.html", domain, encode_url(x)), y))
+            .collect()
     };
     let mut sitemap = Vec::new();
     let builder = sitemap::writer::SiteMapWriter::new(&mut sitemap);
     let mut url_set = builder.start_urlset()?;
-    url_set.url(UrlEntry::builder().loc(request.state().domain.as_str()).priority(1.0).build()?)?;
-    url_set.url(UrlEntry::builder().loc(format!("{}/posts", request.state().domain.as_str())).priority(0.8).build()?)?;
-    url_set.url(UrlEntry::builder().loc(format!("{}/tags", request.state().domain.as_str())).priority(0.8).build()?)?;
+    url_set.url(UrlEntry::builder().loc(domain).priority(1.0).build()?)?;
+    url_set.url(UrlEntry::builder().loc(format!("{}/posts", domain)).priority(0.8).build()?)?;
+    url_set.url(UrlEntry::builder().loc(format!("{}/tags", domain)).priority(0.8).build()?)?;
     for i in pages {
         url_set.url(UrlEntry::builder().loc(i.0).priority(if i.1 { 0.7 } else { 0.6 }).build()?)?;
     }
@@ -582,11 +876,5 @@ pub async fn handle_sitemap(request: Request<ServerState>) -> tide::Result<Respo
                                                                , chrono::FixedOffset::east(0))).build()?)?;
     }
     url_set.end()?;
-    let mime = http_types::mime::Mime::from_str("application/xml")?;
-    let mut response = Response::new(StatusCode::Ok);
-    response.set_content_type(mime);
-    response.set_body(sitemap);
-    Ok(
-        response
-    )
-}
\ No newline at end of file
+    Ok(sitemap)
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use async_diesel::*;
+use diesel::prelude::*;
+
+use crate::{ConnPool, PAGE_LIMIT};
+use crate::markdown::Extensions;
+use crate::model::{Page, Post, POST_COLUMNS};
+use crate::server::{render_atom, render_index, render_page, render_post_page, render_posts, render_rss,
+                    render_sitemap, render_tag, render_tags, tag_counts};
+
+/// Writes the pages the server would serve into a directory, laid out so that the links in the
+/// templates keep working on a static host: `/posts/1` becomes `posts/1/index.html` and
+/// `/post/<slug>.html` stays as it is. Search, comments and `/lucky` need the server.
+pub struct StaticSite<'a> {
+    pool: &'a ConnPool,
+    blog_name: &'a str,
+    domain: &'a str,
+    output: PathBuf,
+    comment_extensions: Extensions,
+    written: usize,
+}
+
+fn rendered<T>(result: tide::Result<T>) -> anyhow::Result<T> {
+    result.map_err(|e| e.into_inner())
+}
+
+/// Listing pages link to the next page whenever the current one is not empty, so the empty page
+/// after the last full one is rendered as well.
+fn page_count(posts: i64) -> i64 {
+    if posts == 0 { 1 } else { (posts - 1) / PAGE_LIMIT + 2 }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> anyhow::Result<usize> {
+    let mut count = 0;
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            count += copy_dir(entry.path().as_path(), target.as_path())?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+impl<'a> StaticSite<'a> {
+    pub fn new<P: AsRef<Path>>(pool: &'a ConnPool, blog_name: &'a str, domain: &'a str, output: P,
+                               comment_extensions: Extensions) -> Self {
+        StaticSite {
+            pool,
+            blog_name,
+            domain,
+            output: output.as_ref().to_path_buf(),
+            comment_extensions,
+            written: 0,
+        }
+    }
+
+    fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&mut self, path: P, content: C) -> anyhow::Result<()> {
+        let target = self.output.join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(target, content)?;
+        self.written += 1;
+        Ok(())
+    }
+
+    async fn build_posts(&mut self) -> anyhow::Result<()> {
+        use crate::schema::posts::dsl::*;
+        let live: Vec<Post> = posts
+            .select(POST_COLUMNS)
+            .filter(Post::is_live())
+            .order_by(id)
+            .load_async::<Post>(self.pool)
+            .await?;
+        for page_number in 0..page_count(live.len() as i64) {
+            let page = rendered(render_posts(self.pool, self.blog_name, page_number).await)?;
+            if page_number == 0 {
+                self.write("posts/index.html", page.as_str())?;
+            }
+            self.write(format!("posts/{}/index.html", page_number), page)?;
+        }
+        for post in live {
+            self.write(format!("raw/post/{}", post.id), post.content.as_str())?;
+            let name = format!("post/{}.html", post.slug);
+            let page = rendered(render_post_page(self.pool, self.blog_name, post, None, self.comment_extensions).await)?;
+            self.write(name, page)?;
+        }
+        Ok(())
+    }
+
+    async fn build_pages(&mut self) -> anyhow::Result<()> {
+        use crate::schema::pages::dsl::*;
+        let all_pages = pages.load_async::<Page>(self.pool).await?;
+        for page in all_pages.iter() {
+            let rendered_page = rendered(render_page(self.pool, self.blog_name, page).await)?;
+            self.write(format!("page/{}.html", page.slug), rendered_page)?;
+            self.write(format!("raw/page/{}", page.id), page.content.as_str())?;
+        }
+        Ok(())
+    }
+
+    async fn build_tags(&mut self) -> anyhow::Result<()> {
+        let tags = rendered(tag_counts(self.pool).await)?;
+        for tag in tags.iter() {
+            if tag.tag.contains('/') {
+                log::warn!("skipped tag {}, it cannot be a directory name", tag.tag);
+                continue;
+            }
+            let dir = tag.tag.replace(" ", "-");
+            for page_number in 0..page_count(tag.count as i64) {
+                let page = rendered(render_tag(self.pool, self.blog_name, tag.tag.as_str(), page_number).await)?;
+                if page_number == 0 {
+                    self.write(format!("tag/{}/index.html", dir), page.as_str())?;
+                }
+                self.write(format!("tag/{}/{}/index.html", dir, page_number), page)?;
+            }
+        }
+        self.write("tags/index.html", rendered(render_tags(self.blog_name, tags))?)
+    }
+
+    async fn build_comments(&mut self) -> anyhow::Result<()> {
+        use crate::schema::comments::dsl::*;
+        let signatures = comments
+            .select((id, signature))
+            .filter(approved)
+            .load_async::<(i32, String)>(self.pool)
+            .await?;
+        for (cid, content) in signatures {
+            self.write(format!("raw/comment/{}", cid), content)?;
+        }
+        Ok(())
+    }
+
+    /// Render everything and copy `static` from the web root; returns the number of files written.
+    pub async fn build<P: AsRef<Path>>(mut self, web_root: P, highlight_css: String) -> anyhow::Result<usize> {
+        let index = rendered(render_index(self.pool, self.blog_name).await)?;
+        self.write("index.html", index)?;
+        self.write("highlight.css", highlight_css)?;
+        self.build_posts().await?;
+        self.build_pages().await?;
+        self.build_tags().await?;
+        self.build_comments().await?;
+        let rss = rendered(render_rss(self.pool, self.blog_name, self.domain).await)?;
+        self.write("rss.xml", rss)?;
+        let atom = rendered(render_atom(self.pool, self.blog_name, self.domain).await)?;
+        self.write("atom.xml", atom)?;
+        let sitemap = rendered(render_sitemap(self.pool, self.domain).await)?;
+        self.write("sitemap.xml", sitemap)?;
+        let assets = copy_dir(web_root.as_ref().join("static").as_path(), self.output.join("static").as_path())?;
+        Ok(self.written + assets)
+    }
+}
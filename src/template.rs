@@ -1,14 +1,21 @@
 use askama::*;
 
-use crate::model::{Comment, Page, Post};
+use crate::markdown::{Extensions, Heading};
+use crate::model::{Page, Post, ThreadedComment};
 use chrono::Datelike;
 
 #[derive(Template)]
 #[template(path = "post.html")]
 pub struct PostTemplate<'a> {
     pub post: Post,
-    pub comments: Vec<Comment>,
+    pub content: String,
+    pub table_of_contents: Option<Vec<Heading>>,
+    pub comments: Vec<ThreadedComment>,
+    pub comment_extensions: Extensions,
     pub blog_name: &'a str,
+    pub read_only: bool,
+    pub challenge: String,
+    pub difficulty: u32,
 }
 
 #[derive(Template)]
@@ -53,7 +60,7 @@ pub struct Tag {
 
 impl Tag {
     pub fn translate_name(&self) -> String {
-        self.tag.replace(" ", "-")
+        crate::utils::encode_url(self.tag.replace(" ", "-"))
     }
 }
 
@@ -77,6 +84,7 @@ pub struct RemoveCommentTemplate<'a> {
 pub struct PageTemplate<'a> {
     pub blog_name: &'a str,
     pub page: &'a Page,
+    pub content: String,
 }
 
 #[derive(Template)]
@@ -87,6 +95,20 @@ pub struct IndexTemplate<'a> {
     pub important_pages: &'a [(String, i32, String)],
 }
 
+/// A standalone page for `client preview`, styled with the stylesheets of `site`. With a
+/// `version` it polls `/version` and reloads when the preview server has a newer one.
+#[derive(Template)]
+#[template(path = "preview.html")]
+pub struct PreviewTemplate<'a> {
+    pub site: &'a str,
+    pub title: &'a str,
+    pub tags: &'a [String],
+    pub content: &'a str,
+    pub abstract_content: Option<&'a str>,
+    pub table_of_contents: Option<&'a [Heading]>,
+    pub version: Option<u64>,
+}
+
 pub trait BaseTemplate {
     fn get_year(&self) -> i32 {
         chrono::Utc::today().year()
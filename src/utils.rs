@@ -7,8 +7,6 @@ use serde::Serialize;
 use serde_json::*;
 use tide::{Status, StatusCode};
 
-static KEY_REGEX: &str = r#"Primary key fingerprint: (.+)|Good signature from (".+" \[ultimate\])"#;
-
 pub async fn simdjson_body<T, State>(req: &mut tide::Request<State>) -> tide::Result<T>
     where for<'a> T: serde::de::Deserialize<'a> {
     let mut res = req.body_bytes()
@@ -16,28 +14,59 @@ pub async fn simdjson_body<T, State>(req: &mut tide::Request<State>) -> tide::Re
     simd_json::from_slice(res.as_mut_slice()).status(StatusCode::UnprocessableEntity)
 }
 
-pub(crate) fn gpg_decrypt(content: &str) -> anyhow::Result<(String, String)> {
-    let mut to_verify = tempfile::NamedTempFile::new()?;
-    to_verify.write(content.as_bytes())?;
-    to_verify.flush()?;
-    let output = std::process::Command::new("gpg")
-        .arg("--decrypt")
-        .arg(to_verify.path())
-        .output()?;
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("invalid signature"));
+const URL_ENCODE_SET: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_');
+
+/// Turn a title into the url fragment used by `/post/<slug>.html` and `/page/<slug>.html`.
+/// Letters and digits of any script are kept in lower case, everything else (spaces,
+/// punctuation like `:` or `?`) collapses into single dashes.
+pub fn slugify<S: AsRef<str>>(title: S) -> String {
+    let mut slug = String::with_capacity(title.as_ref().len());
+    for i in title.as_ref().chars() {
+        if i.is_alphanumeric() || i == '_' {
+            slug.extend(i.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
     }
-    let real_content: String = String::from_utf8(output.stdout)?;
-    let content = String::from_utf8(output.stderr)?;
-    let re = regex::Regex::new(KEY_REGEX)?;
-    re.captures(content.as_str())
-        .take()
-        .and_then(|x| match x.get(2) {
-            Some(e) => Some(e),
-            None => x.get(1)
-        })
-        .map(|x| (real_content, x.as_str().to_string()))
-        .ok_or(anyhow::anyhow!("wrong format!"))
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+/// Percent-encode a slug or a tag name so that it can be put into links, feeds and headers.
+pub fn encode_url<S: AsRef<str>>(name: S) -> String {
+    percent_encoding::utf8_percent_encode(name.as_ref(), URL_ENCODE_SET).to_string()
+}
+
+/// Normalize tags the way they are stored in the database: trimmed, lower case, sorted and unique.
+pub fn normalize_tags<I: IntoIterator<Item=String>>(tags: I) -> Vec<String> {
+    let mut tags: Vec<String> = tags.into_iter()
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Hex SHA-256 over length prefixed parts, so that moving text from one part to the next
+/// still changes the digest.
+pub fn content_digest<I, S>(parts: I) -> anyhow::Result<String>
+    where I: IntoIterator<Item=S>, S: AsRef<[u8]> {
+    let hash = botan::HashFunction::new("SHA-256")
+        .map_err(|e| anyhow!("{:?}", e))?;
+    for part in parts {
+        let part = part.as_ref();
+        hash.update(&(part.len() as u64).to_be_bytes())
+            .and_then(|_| hash.update(part))
+            .map_err(|e| anyhow!("{:?}", e))?;
+    }
+    hash.finish()
+        .map(hex::encode)
+        .map_err(|e| anyhow!("{:?}", e))
 }
 
 pub fn to_table<T: Serialize>(s: &T) -> anyhow::Result<Table> {
@@ -130,54 +159,22 @@ pub fn confirm<S: AsRef<str>>(msg: S) -> anyhow::Result<()> {
     }
 }
 
-use katex::{Opts, OutputType};
+#[cfg(test)]
+mod test {
+    use super::{encode_url, slugify};
 
-pub trait RenderKaTeX {
-    fn render_katex(&self) -> tide::Result<String>;
-}
+    #[test]
+    fn slugify_unicode_title() {
+        assert_eq!(slugify("Hello World"), "hello-world");
+        assert_eq!(slugify("Rust: What is Pin?"), "rust-what-is-pin");
+        assert_eq!(slugify("Über  Ärger"), "über-ärger");
+        assert_eq!(slugify("你好，世界"), "你好-世界");
+    }
 
-impl<S: AsRef<str>> RenderKaTeX for S {
-    fn render_katex(&self) -> tide::Result<String> {
-        let content = self.as_ref();
-        let mut buffer = String::with_capacity(1024);
-        let mut sub_buffer = String::new();
-        let mut status = 0;
-        for i in content.chars() {
-            status = match (status, i) {
-                (0, '$') => 1,
-                (1, '$') => 2, // display
-                (1, _) => 5, // simple
-                (2, '$') => {
-                    let res = katex::render_with_opts(sub_buffer.as_str(), Opts::builder()
-                        .display_mode(false)
-                        .output_type(OutputType::Html)
-                        .build()
-                        .unwrap())?;
-                    buffer.push_str(&res);
-                    sub_buffer.clear();
-                    3
-                },
-                (3, '$') => 0,
-                (5, '$') => {
-                    let res = katex::render_with_opts(sub_buffer.as_str(), Opts::builder()
-                        .display_mode(false)
-                        .output_type(OutputType::Html)
-                        .build()
-                        .unwrap())?;
-                    buffer.push_str(&res);
-                    sub_buffer.clear();
-                    0
-                }
-                (_, _) => status,
-            };
-            if i != '$' {
-                if status != 2 && status != 5 {
-                    buffer.push(i);
-                } else {
-                    sub_buffer.push(i);
-                }
-            }
-        }
-        Ok(buffer)
+    #[test]
+    fn encode_unicode_slug() {
+        assert_eq!(encode_url("hello-world"), "hello-world");
+        assert_eq!(encode_url("über"), "%C3%BCber");
+        assert_eq!(encode_url("rust lang"), "rust%20lang");
     }
-}
\ No newline at end of file
+}